@@ -0,0 +1,17 @@
+use std::process::Command;
+
+fn main() {
+    // The hash is optional, e.g. when building from a source archive
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|v| v.status.success())
+        .and_then(|v| String::from_utf8(v.stdout).ok())
+        .map(|v| v.trim().to_string())
+        .unwrap_or_default();
+
+    println!("cargo:rustc-env=VPUPPR_GIT_COMMIT_HASH={hash}");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
@@ -1,4 +1,4 @@
-use std::{fmt::Display, str::FromStr};
+use std::{fmt::Display, path::Path, str::FromStr};
 
 use argh::FromArgs;
 use godot::prelude::{Dictionary, GodotString};
@@ -10,15 +10,54 @@ pub enum CliError {
     ParseFailure(argh::EarlyExit),
     UnknownTracker { input: String },
     UnknownModelType { input: String },
+    InvalidPort { input: u32 },
+    BackgroundWithoutModel,
 }
 
 impl Display for CliError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::ParseFailure(e) => write!(f, "{e:?}"),
+            Self::ParseFailure(e) => write!(f, "{}", e.output.trim()),
             Self::UnknownTracker { input } => write!(f, "Unknown tracker: {input}"),
             Self::UnknownModelType { input } => write!(f, "Unknown model type: {input}"),
+            Self::InvalidPort { input } => {
+                write!(f, "Invalid port: {input}, must be between 1 and 65535")
+            }
+            Self::BackgroundWithoutModel => write!(
+                f,
+                "Running in the background requires the launch or with-model command"
+            ),
+        }
+    }
+}
+
+impl CliError {
+    /// Whether parsing stopped early because help was requested, which is not an error.
+    pub fn is_help(&self) -> bool {
+        matches!(self, Self::ParseFailure(e) if e.status.is_ok())
+    }
+
+    /// Convert self to a [Dictionary] containing an `error` message. Parse failures
+    /// also contain the `help_text` for all args.
+    ///
+    /// If help was requested, the [Dictionary] only contains the `help_text`.
+    pub fn to_dict(&self) -> Dictionary {
+        let mut r = Dictionary::new();
+
+        match self {
+            Self::ParseFailure(e) if self.is_help() => {
+                r.insert("help_text", GodotString::from(&e.output));
+            }
+            Self::ParseFailure(_) => {
+                r.insert("error", GodotString::from(self.to_string()));
+                r.insert("help_text", GodotString::from(Args::help_text()));
+            }
+            _ => {
+                r.insert("error", GodotString::from(self.to_string()));
+            }
         }
+
+        r
     }
 }
 
@@ -34,6 +73,13 @@ pub struct Args {
     /// limit the max fps to the given value
     #[argh(option)]
     max_fps: Option<u32>,
+    /// run tracking without rendering a window, requires "launch" or "with-model".
+    /// Logging is unaffected, use "quiet" to disable it
+    #[argh(switch, short = 'b', long = "background")]
+    background: bool,
+    /// print the vpuppr version
+    #[argh(switch, long = "version")]
+    version: bool,
     #[argh(subcommand)]
     commands: Option<Commands>,
 }
@@ -41,7 +87,35 @@ pub struct Args {
 impl Args {
     /// Parse some `args`. Args are expected to come from Godot user args.
     pub fn parse(args: &[&str]) -> Result<Self, CliError> {
-        Self::from_args(&[env!("CARGO_PKG_NAME")], args).map_err(|e| CliError::ParseFailure(e))
+        let args = Self::from_args(&[env!("CARGO_PKG_NAME")], args)
+            .map_err(|e| CliError::ParseFailure(e))?;
+        args.validate()?;
+
+        Ok(args)
+    }
+
+    /// The `--version` text.
+    pub fn version_text() -> String {
+        format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+    }
+
+    /// The `--help` text for all args.
+    pub fn help_text() -> String {
+        match Self::from_args(&[env!("CARGO_PKG_NAME")], &["--help"]) {
+            Ok(_) => String::new(),
+            Err(e) => e.output,
+        }
+    }
+
+    /// Check values that cannot be checked while parsing.
+    fn validate(&self) -> Result<(), CliError> {
+        match &self.commands {
+            Some(Commands::Launch(c)) => c.validate(),
+            Some(Commands::WithModel(c)) => c.model_type().map(|_| ()),
+            // There is nothing to track without a model
+            _ if self.background => Err(CliError::BackgroundWithoutModel),
+            _ => Ok(()),
+        }
     }
 
     /// Convert self to a [Dictionary].
@@ -54,6 +128,16 @@ impl Args {
         r.insert("verbose", self.verbose);
         r.insert("quiet", self.quiet);
         r.insert("max_fps", self.max_fps.unwrap_or(0));
+        r.insert("background", self.background);
+        // Shown by the launcher instead of starting if not empty
+        r.insert(
+            "help_text",
+            if self.version {
+                GodotString::from(Self::version_text())
+            } else {
+                GodotString::new()
+            },
+        );
 
         if let Some(c) = &self.commands {
             r.insert("has_command", true);
@@ -64,6 +148,7 @@ impl Args {
             match c {
                 Commands::Launch(c) => c.populate_dict(&mut r),
                 Commands::WithModel(c) => c.populate_dict(&mut r),
+                Commands::List(c) => c.populate_dict(&mut r),
             }
         } else {
             r.insert("has_command", false);
@@ -82,6 +167,7 @@ trait GodotCommand {
 pub enum Commands {
     Launch(LaunchCommand),
     WithModel(WithModelCommand),
+    List(ListCommand),
 }
 
 /// Launch vpuppr with some options
@@ -94,6 +180,23 @@ pub struct LaunchCommand {
     /// tracker to start upon launch
     #[argh(option)]
     tracker: Option<Tracker>,
+    /// override the address of the tracker
+    #[argh(option)]
+    address: Option<String>,
+    /// override the port of the tracker, from 1-65535
+    #[argh(option)]
+    port: Option<u32>,
+}
+
+impl LaunchCommand {
+    fn validate(&self) -> Result<(), CliError> {
+        match self.port {
+            Some(v) if !(1..=u16::MAX as u32).contains(&v) => {
+                Err(CliError::InvalidPort { input: v })
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 impl GodotCommand for LaunchCommand {
@@ -109,10 +212,45 @@ impl GodotCommand for LaunchCommand {
                 GodotString::new()
             },
         );
+        dict.insert(
+            "address",
+            if let Some(v) = &self.address {
+                GodotString::from(v)
+            } else {
+                GodotString::new()
+            },
+        );
+        dict.insert("port", self.port.unwrap_or(0));
+        dict.insert(
+            "receiver",
+            GodotString::from(
+                self.tracker
+                    .as_ref()
+                    .and_then(|v| v.receiver_class())
+                    .unwrap_or_default(),
+            ),
+        );
+    }
+}
+
+/// List saved runner data
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "list")]
+pub struct ListCommand {
+    /// only list favorited runner data
+    #[argh(switch)]
+    favorites_only: bool,
+}
+
+impl GodotCommand for ListCommand {
+    fn populate_dict(&self, dict: &mut Dictionary) {
+        dict.insert("command", "list");
+
+        dict.insert("favorites_only", self.favorites_only);
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Tracker {
     MediaPipe,
     IFacialMocap,
@@ -147,6 +285,19 @@ impl FromStr for Tracker {
     }
 }
 
+impl Tracker {
+    /// The name of the receiver class that handles this tracker, if the tracker is
+    /// received in Rust. MeowFace uses the same protocol as VTubeStudio.
+    pub fn receiver_class(&self) -> Option<&'static str> {
+        match self {
+            Tracker::IFacialMocap => Some("IFacialMocap"),
+            Tracker::VTubeStudio | Tracker::MeowFace => Some("VTubeStudio"),
+            Tracker::OpenSeeFace => Some("OpenSeeFace"),
+            Tracker::MediaPipe | Tracker::Custom(_) => None,
+        }
+    }
+}
+
 impl AsRef<str> for Tracker {
     fn as_ref(&self) -> &str {
         match self {
@@ -178,6 +329,29 @@ pub struct WithModelCommand {
     gui_path: Option<String>,
 }
 
+impl WithModelCommand {
+    /// The forced model type, otherwise the model type inferred from the extension
+    /// of the model path.
+    fn model_type(&self) -> Result<ModelType, CliError> {
+        if let Some(v) = &self.model_type {
+            return Ok(v.clone());
+        }
+
+        match Path::new(&self.model_path)
+            .extension()
+            .map(|v| v.to_string_lossy().to_lowercase())
+            .as_deref()
+        {
+            Some("glb") => Ok(ModelType::Glb),
+            Some("vrm") => Ok(ModelType::Vrm),
+            Some("png") => Ok(ModelType::PngTuber),
+            _ => Err(CliError::UnknownModelType {
+                input: self.model_path.clone(),
+            }),
+        }
+    }
+}
+
 impl GodotCommand for WithModelCommand {
     fn populate_dict(&self, dict: &mut Dictionary) {
         dict.insert("command", "with_model");
@@ -185,10 +359,9 @@ impl GodotCommand for WithModelCommand {
         dict.insert("model_path", GodotString::from(&self.model_path));
         dict.insert(
             "model_type",
-            if let Some(v) = &self.model_type {
-                GodotString::from(v)
-            } else {
-                GodotString::new()
+            match self.model_type() {
+                Ok(v) => GodotString::from(&v),
+                Err(_) => GodotString::new(),
             },
         );
         dict.insert(
@@ -210,7 +383,7 @@ impl GodotCommand for WithModelCommand {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ModelType {
     Glb,
     Vrm,
@@ -264,6 +437,42 @@ mod tests {
         assert_eq!(args.quiet, false);
     }
 
+    #[test]
+    fn parse_failure_message() {
+        let e = Args::parse(&["--unknown"]).unwrap_err();
+
+        assert!(matches!(e, CliError::ParseFailure(_)));
+        assert!(e.to_string().contains("--unknown"));
+    }
+
+    #[test]
+    fn help_is_not_an_error() {
+        let e = Args::parse(&["--help"]).unwrap_err();
+        assert!(e.is_help());
+
+        let e = Args::parse(&["launch", "--help"]).unwrap_err();
+        assert!(e.is_help());
+
+        let e = Args::parse(&["--unknown"]).unwrap_err();
+        assert!(!e.is_help());
+    }
+
+    #[test]
+    fn version() {
+        let args = Args::parse(&["--version"]).unwrap();
+
+        assert!(args.version);
+        assert!(Args::version_text().ends_with(env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn help_text() {
+        let help_text = Args::help_text();
+
+        assert!(help_text.starts_with(&format!("Usage: {}", env!("CARGO_PKG_NAME"))));
+        assert!(help_text.contains("--verbose"));
+    }
+
     #[test]
     fn empty() {
         let args = Args::from_args(&["vpuppr"], &[]).unwrap();
@@ -297,6 +506,31 @@ mod tests {
         assert_eq!(args.quiet, true);
     }
 
+    #[test]
+    fn background() {
+        let args = Args::parse(&["--background", "--quiet", "launch", "blah"]).unwrap();
+
+        assert_eq!(args.background, true);
+        assert_eq!(args.quiet, true);
+
+        let args = Args::parse(&["-b", "with-model", "./blah.vrm"]).unwrap();
+
+        assert_eq!(args.background, true);
+        assert_eq!(args.verbose, false);
+    }
+
+    #[test]
+    fn background_without_model() {
+        assert!(matches!(
+            Args::parse(&["--background"]),
+            Err(CliError::BackgroundWithoutModel)
+        ));
+        assert!(matches!(
+            Args::parse(&["--background", "list"]),
+            Err(CliError::BackgroundWithoutModel)
+        ));
+    }
+
     mod launch {
         use super::*;
 
@@ -355,6 +589,66 @@ mod tests {
             }
         }
 
+        #[test]
+        fn open_see_face_tracker() {
+            let args =
+                Args::from_args(&["vpuppr"], &["launch", "blah", "--tracker", "osf"]).unwrap();
+
+            match args.commands.unwrap() {
+                Commands::Launch(v) => {
+                    let tracker = v.tracker.unwrap();
+                    assert_eq!(tracker, Tracker::OpenSeeFace);
+                    assert_eq!(tracker.as_ref(), "openseeface");
+                    assert_eq!(tracker.receiver_class(), Some("OpenSeeFace"));
+                }
+                _ => assert!(false),
+            }
+        }
+
+        #[test]
+        fn receiver_classes() {
+            assert_eq!(Tracker::MeowFace.receiver_class(), Some("VTubeStudio"));
+            assert_eq!(Tracker::IFacialMocap.receiver_class(), Some("IFacialMocap"));
+            assert_eq!(Tracker::MediaPipe.receiver_class(), None);
+            assert_eq!(Tracker::Custom("woo".to_string()).receiver_class(), None);
+        }
+
+        #[test]
+        fn address_and_port() {
+            let args = Args::parse(&[
+                "launch",
+                "mymodel",
+                "--tracker",
+                "ifm",
+                "--address",
+                "192.168.1.5",
+                "--port",
+                "49983",
+            ])
+            .unwrap();
+
+            match args.commands.unwrap() {
+                Commands::Launch(v) => {
+                    assert_eq!(v.tracker.unwrap(), Tracker::IFacialMocap);
+                    assert_eq!(v.address.unwrap(), "192.168.1.5");
+                    assert_eq!(v.port.unwrap(), 49983);
+                }
+                _ => assert!(false),
+            }
+        }
+
+        #[test]
+        fn invalid_port() {
+            for port in ["0", "65536"] {
+                let args = Args::parse(&["launch", "blah", "--port", port]);
+
+                assert!(matches!(args, Err(CliError::InvalidPort { .. })), "{port}");
+            }
+
+            let args = Args::parse(&["launch", "blah", "--port", "-1"]);
+            assert!(args.is_err());
+        }
+
         #[test]
         fn custom_tracker() {
             let args = Args::from_args(&["vpuppr"], &["launch", "blah", "--tracker", "custom:woo"])
@@ -523,6 +817,42 @@ mod tests {
             }
         }
 
+        #[test]
+        fn infer_model_type() {
+            for (path, model_type) in [
+                ("./blah.glb", ModelType::Glb),
+                ("./blah.vrm", ModelType::Vrm),
+                ("./blah.png", ModelType::PngTuber),
+                ("./BLAH.VRM", ModelType::Vrm),
+            ] {
+                let args = Args::parse(&["with-model", path]).unwrap();
+
+                match args.commands.unwrap() {
+                    Commands::WithModel(v) => {
+                        assert!(v.model_type.is_none());
+                        assert_eq!(v.model_type().unwrap(), model_type);
+                    }
+                    _ => assert!(false),
+                }
+            }
+        }
+
+        #[test]
+        fn unknown_extension() {
+            for path in ["./blah.fbx", "./blah"] {
+                let args = Args::parse(&["with-model", path]);
+
+                assert!(
+                    matches!(args, Err(CliError::UnknownModelType { .. })),
+                    "{path}"
+                );
+            }
+
+            // Forcing the model type skips inference
+            let args = Args::parse(&["with-model", "./blah.fbx", "--model-type", "glb"]);
+            assert!(args.is_ok());
+        }
+
         #[test]
         fn model_type_ignore_case() {
             let args = Args::from_args(
@@ -542,4 +872,35 @@ mod tests {
             }
         }
     }
+
+    mod list {
+        use super::*;
+
+        #[test]
+        fn all() {
+            let args = Args::from_args(&["vpuppr"], &["list"]).unwrap();
+
+            match args.commands.unwrap() {
+                Commands::List(v) => assert!(!v.favorites_only),
+                _ => assert!(false),
+            }
+        }
+
+        #[test]
+        fn favorites_only() {
+            let args = Args::from_args(&["vpuppr"], &["list", "--favorites-only"]).unwrap();
+
+            match args.commands.unwrap() {
+                Commands::List(v) => assert!(v.favorites_only),
+                _ => assert!(false),
+            }
+        }
+
+        #[test]
+        fn unexpected_positional() {
+            let args = Args::from_args(&["vpuppr"], &["list", "blah"]);
+
+            assert!(args.is_err());
+        }
+    }
 }
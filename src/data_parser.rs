@@ -1,117 +1,196 @@
+use aho_corasick::AhoCorasick;
 use godot::prelude::*;
 use log::error;
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
+use crate::model::IFacialMocapData;
+
+/// Side suffixes used by ARKit-style blend shape names and their replacements.
+const BLEND_SHAPE_SIDES: [(&str, &str); 2] = [("_L", "left"), ("_R", "right")];
+static BLEND_SHAPE_SIDE_MATCHER: Lazy<AhoCorasick> = Lazy::new(|| {
+    AhoCorasick::new(BLEND_SHAPE_SIDES.map(|(k, _)| k)).expect("Side suffixes must be valid")
+});
+
+/// Number of 2D landmarks sent by OpenSeeFace.
+const OSF_LANDMARK_COUNT: usize = 68;
+/// Number of 3D points sent by OpenSeeFace.
+const OSF_POINT_3D_COUNT: usize = 70;
+/// Names of the features sent by OpenSeeFace, in order.
+const OSF_FEATURES: [&str; 14] = [
+    "eye_l",
+    "eye_r",
+    "eyebrow_steepness_l",
+    "eyebrow_updown_l",
+    "eyebrow_quirk_l",
+    "eyebrow_steepness_r",
+    "eyebrow_updown_r",
+    "eyebrow_quirk_r",
+    "mouth_corner_updown_l",
+    "mouth_corner_inout_l",
+    "mouth_corner_updown_r",
+    "mouth_corner_inout_r",
+    "mouth_open",
+    "mouth_wide",
+];
+/// The size of a single OpenSeeFace packet in bytes.
+pub const OSF_PACKET_SIZE: usize = 8 // timestamp
+    + 4 // face id
+    + (2 * 4) // width, height
+    + (2 * 4) // eye blinks
+    + 1 // success
+    + 4 // pnp error
+    + (4 * 4) // quaternion
+    + (3 * 4) // euler
+    + (3 * 4) // translation
+    + (OSF_LANDMARK_COUNT * 4) // landmark confidences
+    + (OSF_LANDMARK_COUNT * 2 * 4) // landmarks
+    + (OSF_POINT_3D_COUNT * 3 * 4) // 3d points
+    + (OSF_FEATURES.len() * 4);
+
+/// A decoded OpenSeeFace packet. All values are little-endian on the wire.
+#[derive(Debug, Default, PartialEq)]
+pub struct OpenSeeFaceData {
+    pub timestamp: f64,
+    pub face_id: i32,
+    pub width: f32,
+    pub height: f32,
+    pub right_eye_open: f32,
+    pub left_eye_open: f32,
+    pub success: bool,
+    pub pnp_error: f32,
+    pub quaternion: Quaternion,
+    pub rotation: Vector3,
+    pub translation: Vector3,
+    pub features: Vec<(&'static str, f32)>,
+}
+
+impl OpenSeeFaceData {
+    /// Decode a single packet. Returns `None` if the packet is truncated.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < OSF_PACKET_SIZE {
+            return None;
+        }
+
+        let mut reader = LeReader { data, pos: 0 };
+
+        let mut r = Self {
+            timestamp: reader.f64(),
+            face_id: reader.i32(),
+            width: reader.f32(),
+            height: reader.f32(),
+            right_eye_open: reader.f32(),
+            left_eye_open: reader.f32(),
+            success: reader.u8() != 0,
+            pnp_error: reader.f32(),
+            ..Default::default()
+        };
+        r.quaternion = Quaternion::new(reader.f32(), reader.f32(), reader.f32(), reader.f32());
+        r.rotation = reader.vector3();
+        r.translation = reader.vector3();
+
+        // Landmark confidences, landmarks, and 3d points are not used
+        reader.skip((OSF_LANDMARK_COUNT * 3 + OSF_POINT_3D_COUNT * 3) * 4);
+
+        r.features = OSF_FEATURES.iter().map(|v| (*v, reader.f32())).collect();
+
+        Some(r)
+    }
+
+    /// Convert the packet to a [Dictionary]. See [DataParser::open_see_face] for the keys.
+    pub fn to_dict(&self) -> Dictionary {
+        let mut r = Dictionary::new();
+
+        r.insert("face_id", self.face_id);
+        r.insert("success", self.success);
+        r.insert("quaternion", self.quaternion);
+        r.insert("rotation", self.rotation);
+        r.insert("translation", self.translation);
+        r.insert("right_eye_open", self.right_eye_open);
+        r.insert("left_eye_open", self.left_eye_open);
+        r.insert(
+            "features",
+            self.features.iter().copied().collect::<Dictionary>(),
+        );
+
+        r
+    }
+}
+
+/// Reads little-endian values from a byte slice. Callers must make sure the slice
+/// is large enough.
+struct LeReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> LeReader<'a> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let mut r = [0; N];
+        r.copy_from_slice(&self.data[self.pos..self.pos + N]);
+        self.pos += N;
+
+        r
+    }
+
+    fn skip(&mut self, len: usize) {
+        self.pos += len;
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take::<1>()[0]
+    }
+
+    fn i32(&mut self) -> i32 {
+        i32::from_le_bytes(self.take())
+    }
+
+    fn f32(&mut self) -> f32 {
+        f32::from_le_bytes(self.take())
+    }
+
+    fn f64(&mut self) -> f64 {
+        f64::from_le_bytes(self.take())
+    }
+
+    fn vector3(&mut self) -> Vector3 {
+        Vector3::new(self.f32(), self.f32(), self.f32())
+    }
+}
+
 #[derive(Debug, GodotClass)]
 #[class(init)]
 pub struct DataParser;
 
 #[godot_api]
 impl DataParser {
+    /// Parse an iFacialMocap packet. See [IFacialMocapData::parse] for how the
+    /// packet is handled.
     #[func]
     pub fn ifacial_mocap(data: PackedByteArray) -> Dictionary {
         let mut r = Dictionary::new();
-        let mut blend_shapes = Dictionary::new();
-
-        match std::str::from_utf8(data.as_slice()) {
-            Ok(v) => {
-                let mut split = v.split("|");
-                while let Some(v) = split.next() {
-                    if let Some((k, v)) = v.split_once('#') {
-                        // TODO these are all gross, there must be a better way
-                        match k {
-                            "=head" => {
-                                let vals = v.splitn(5, ',').collect::<Vec<&str>>();
-
-                                r.insert(
-                                    "rotation",
-                                    Vector3::new(
-                                        vals.get(0)
-                                            .map(|v| v.parse::<f32>().unwrap_or_default())
-                                            .unwrap_or_default(),
-                                        vals.get(1)
-                                            .map(|v| v.parse::<f32>().unwrap_or_default())
-                                            .unwrap_or_default(),
-                                        vals.get(2)
-                                            .map(|v| v.parse::<f32>().unwrap_or_default())
-                                            .unwrap_or_default(),
-                                    ),
-                                );
-
-                                r.insert(
-                                    "position",
-                                    Vector3::new(
-                                        vals.get(3)
-                                            .map(|v| v.parse::<f32>().unwrap_or_default())
-                                            .unwrap_or_default(),
-                                        vals.get(4)
-                                            .map(|v| v.parse::<f32>().unwrap_or_default())
-                                            .unwrap_or_default(),
-                                        vals.get(5)
-                                            .map(|v| v.parse::<f32>().unwrap_or_default())
-                                            .unwrap_or_default(),
-                                    ),
-                                );
-                            }
-                            "rightEye" => {
-                                let vals = v.splitn(2, ',').collect::<Vec<&str>>();
-
-                                r.insert(
-                                    "right_eye",
-                                    Vector3::new(
-                                        vals.get(0)
-                                            .map(|v| v.parse::<f32>().unwrap_or_default())
-                                            .unwrap_or_default(),
-                                        vals.get(1)
-                                            .map(|v| v.parse::<f32>().unwrap_or_default())
-                                            .unwrap_or_default(),
-                                        vals.get(2)
-                                            .map(|v| v.parse::<f32>().unwrap_or_default())
-                                            .unwrap_or_default(),
-                                    ),
-                                );
-                            }
-                            "leftEye" => {
-                                let vals = v.splitn(2, ',').collect::<Vec<&str>>();
-
-                                r.insert(
-                                    "left_eye",
-                                    Vector3::new(
-                                        vals.get(0)
-                                            .map(|v| v.parse::<f32>().unwrap_or_default())
-                                            .unwrap_or_default(),
-                                        vals.get(1)
-                                            .map(|v| v.parse::<f32>().unwrap_or_default())
-                                            .unwrap_or_default(),
-                                        vals.get(2)
-                                            .map(|v| v.parse::<f32>().unwrap_or_default())
-                                            .unwrap_or_default(),
-                                    ),
-                                );
-                            }
-                            _ => error!("Unhandled ifm data key: {k}"),
-                        }
-                    } else if let Some((k, v)) = v.split_once("-") {
-                        blend_shapes.insert(
-                            k
-                                // TODO maybe use https://github.com/BurntSushi/aho-corasick for faster replace?
-                                .replace("_L", "left")
-                                .replace("_R", "right")
-                                .to_lowercase(),
-                            f32::from(v.parse::<i16>().unwrap_or(0)) / 100.0,
-                        );
-                    } else if v.is_empty() {
-                    } else {
-                        error!("Unhandled ifm key-value pair {v}");
-                    }
-                }
-            }
+
+        let data = match std::str::from_utf8(data.as_slice()) {
+            Ok(v) => IFacialMocapData::parse(v),
             Err(e) => {
                 error!("{e}");
+                r.insert("blend_shapes", Dictionary::new());
+                return r;
             }
-        }
+        };
+
+        r.insert("rotation", data.rotation);
+        r.insert("position", data.position);
+        r.insert("right_eye", data.right_eye);
+        r.insert("left_eye", data.left_eye);
+        r.insert(
+            "blend_shapes",
+            data.blend_shapes
+                .iter()
+                .map(|(k, v)| (GodotString::from(k.to_lowercase()), *v))
+                .collect::<Dictionary>(),
+        );
 
-        r.insert("blend_shapes", blend_shapes);
         r
     }
 
@@ -165,4 +244,111 @@ impl DataParser {
 
         r
     }
+
+    /// Parse an OpenSeeFace packet.
+    ///
+    /// # Returns
+    /// A [Dictionary] containing `face_id`, `success`, `quaternion`, `rotation`,
+    /// `translation`, `right_eye_open`, `left_eye_open`, and a `features` [Dictionary].
+    /// Empty if the packet is truncated.
+    #[func]
+    pub fn open_see_face(data: PackedByteArray) -> Dictionary {
+        let data = match OpenSeeFaceData::parse(data.as_slice()) {
+            Some(v) => v,
+            None => {
+                error!(
+                    "Truncated OpenSeeFace packet, expected {OSF_PACKET_SIZE} bytes but got {}",
+                    data.len()
+                );
+                return Dictionary::new();
+            }
+        };
+
+        data.to_dict()
+    }
+}
+
+/// Replace a trailing `_L` or `_R` in a blend shape name with `left` or `right`.
+/// Only suffixes are replaced, so names like `_Lip` are left alone.
+pub fn normalize_blend_shape_name(name: &str) -> String {
+    match BLEND_SHAPE_SIDE_MATCHER
+        .find_iter(name)
+        .find(|m| m.end() == name.len())
+    {
+        Some(m) => format!(
+            "{}{}",
+            &name[..m.start()],
+            BLEND_SHAPE_SIDES[m.pattern().as_usize()].1
+        ),
+        None => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_blend_shape_suffixes() {
+        assert_eq!(normalize_blend_shape_name("mouthSmile_L"), "mouthSmileleft");
+        assert_eq!(normalize_blend_shape_name("browDown_R"), "browDownright");
+        assert_eq!(normalize_blend_shape_name("_Lip"), "_Lip");
+        assert_eq!(
+            normalize_blend_shape_name("mouth_Lower_R"),
+            "mouth_Lowerright"
+        );
+        assert_eq!(normalize_blend_shape_name("jawOpen"), "jawOpen");
+    }
+
+    fn osf_packet() -> Vec<u8> {
+        let mut r = vec![];
+
+        r.extend(1.5_f64.to_le_bytes());
+        r.extend(3_i32.to_le_bytes());
+        for v in [640.0_f32, 480.0, 0.9, 0.8] {
+            r.extend(v.to_le_bytes());
+        }
+        r.push(1);
+        r.extend(0.1_f32.to_le_bytes());
+        for v in [0.0_f32, 0.0, 0.0, 1.0, 10.0, 20.0, 30.0, 1.0, 2.0, 3.0] {
+            r.extend(v.to_le_bytes());
+        }
+        r.extend(vec![
+            0;
+            (OSF_LANDMARK_COUNT * 3 + OSF_POINT_3D_COUNT * 3) * 4
+        ]);
+        for i in 0..OSF_FEATURES.len() {
+            r.extend((i as f32).to_le_bytes());
+        }
+
+        r
+    }
+
+    #[test]
+    fn open_see_face_packet() {
+        let packet = osf_packet();
+        assert_eq!(packet.len(), OSF_PACKET_SIZE);
+
+        let data = OpenSeeFaceData::parse(&packet).unwrap();
+
+        assert_eq!(data.timestamp, 1.5);
+        assert_eq!(data.face_id, 3);
+        assert_eq!(data.width, 640.0);
+        assert_eq!(data.right_eye_open, 0.9);
+        assert_eq!(data.left_eye_open, 0.8);
+        assert!(data.success);
+        assert_eq!(data.quaternion, Quaternion::new(0.0, 0.0, 0.0, 1.0));
+        assert_eq!(data.rotation, Vector3::new(10.0, 20.0, 30.0));
+        assert_eq!(data.translation, Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(data.features[0], ("eye_l", 0.0));
+        assert_eq!(data.features[13], ("mouth_wide", 13.0));
+    }
+
+    #[test]
+    fn open_see_face_truncated_packet() {
+        let packet = osf_packet();
+
+        assert!(OpenSeeFaceData::parse(&packet[..packet.len() - 1]).is_none());
+        assert!(OpenSeeFaceData::parse(&[]).is_none());
+    }
 }
@@ -1,11 +1,13 @@
 mod cli;
 mod data_parser;
+mod loader;
 mod logger;
-// pub mod model;
-// mod puppets;
+pub mod model;
+mod puppets;
+mod receivers;
 
 use godot::{
-    engine::{global::Error, Os},
+    engine::{global::Error, Engine, Os},
     prelude::*,
 };
 use log::{error, LevelFilter};
@@ -25,7 +27,9 @@ impl RefCountedVirtual for LibVpuppr {
 
 #[godot_api]
 impl LibVpuppr {
-    /// Parse user args and return a [Dictionary] containing all args found.
+    /// Parse user args and return a [Dictionary] containing all args found. If parsing
+    /// fails, the [Dictionary] only contains an `error` message and the `help_text`
+    /// instead. If `--help` was passed, the [Dictionary] only contains the `help_text`.
     #[func]
     fn parse_user_args() -> Dictionary {
         let godot_user_args = Os::singleton()
@@ -45,19 +49,25 @@ impl LibVpuppr {
         ) {
             Ok(v) => v.to_dict(),
             Err(e) => {
-                error!("{e}");
-                Dictionary::new()
+                if !e.is_help() {
+                    error!("{e}");
+                }
+                e.to_dict()
             }
         }
     }
 
-    /// Initialize logging of Rust libraries.
+    /// Initialize logging of Rust libraries. Also installs a panic hook so that
+    /// logs are flushed before crashing, and starts flushing logs periodically.
     ///
     /// # Note
     /// A new [String] must be allocated when printing, otherwise Godot is not
     /// able to print anything.
     #[func]
     fn init_rust_log(quiet: bool, verbose: bool) -> Error {
+        logger::install_panic_hook();
+        logger::start_log_flusher();
+
         match youlog::Youlog::new_from_default_env()
             .global_level(if quiet {
                 LevelFilter::Error
@@ -93,6 +103,47 @@ impl LibVpuppr {
         }
     }
 
+    /// Briefly connect to a tracker and report whether it sent any data back
+    /// within `timeout_ms`. See [receivers::test_connection] for the returned keys.
+    #[func]
+    fn test_connection(
+        tracker: GodotString,
+        address: GodotString,
+        port: i64,
+        timeout_ms: i64,
+    ) -> Dictionary {
+        let port = match u16::try_from(port) {
+            Ok(v) => v,
+            Err(_) => {
+                error!("Invalid port {port}");
+
+                let mut r = Dictionary::new();
+                r.insert("ok", false);
+                r.insert("error", GodotString::from(format!("Invalid port {port}")));
+                r.insert("bytes_received", 0);
+                r.insert("sample", PackedByteArray::new());
+
+                return r;
+            }
+        };
+
+        receivers::test_connection(
+            tracker.to_string().as_str(),
+            address.to_string().as_str(),
+            port,
+            timeout_ms.max(0) as u64,
+        )
+    }
+
+    /// Load a model of the given `model_type` from `bytes`, e.g. from an archive or
+    /// a download. No temp files are written.
+    ///
+    /// Returns the root node of the model or `null` if the model could not be loaded.
+    #[func]
+    fn load_from_bytes(bytes: PackedByteArray, model_type: GodotString) -> Option<Gd<Node>> {
+        loader::load_from_bytes(bytes, model_type.to_string().as_str())
+    }
+
     /// A mapping of various vpuppr metadata.
     #[func]
     fn metadata() -> Dictionary {
@@ -109,6 +160,21 @@ impl LibVpuppr {
 
         mapping.insert("LIBVPUPPR_AUTHORS", env!("CARGO_PKG_AUTHORS"));
 
+        mapping.insert("OS", std::env::consts::OS);
+        mapping.insert("ARCH", std::env::consts::ARCH);
+        mapping.insert(
+            "GODOT_VERSION",
+            Engine::singleton()
+                .get_version_info()
+                .get("string")
+                .unwrap_or_else(|| GodotString::new().to_variant()),
+        );
+        // Set by the build script, empty if git was not available while building
+        mapping.insert(
+            "GIT_COMMIT_HASH",
+            option_env!("VPUPPR_GIT_COMMIT_HASH").unwrap_or_default(),
+        );
+
         mapping
     }
 }
@@ -116,4 +182,11 @@ impl LibVpuppr {
 struct GodotExtension;
 
 #[gdextension]
-unsafe impl ExtensionLibrary for GodotExtension {}
+unsafe impl ExtensionLibrary for GodotExtension {
+    fn on_level_deinit(level: InitLevel) {
+        // Singletons are still available at this level, which is needed for flushing
+        if matches!(level, InitLevel::Scene) {
+            logger::stop_log_flusher();
+        }
+    }
+}
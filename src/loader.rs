@@ -0,0 +1,79 @@
+use std::str::FromStr;
+
+use godot::{
+    engine::{global::Error, GltfDocument, GltfState, Image, ImageTexture, Sprite2D},
+    prelude::*,
+};
+use log::error;
+
+use crate::cli::ModelType;
+
+/// Load a model of the given `model_type` from `bytes`. Godot is able to load all
+/// supported model types from memory, so no temp files are written.
+///
+/// # Returns
+/// The root [Node] of the loaded model or `None` if the model could not be loaded.
+/// The caller is responsible for adding the node to the tree or freeing it.
+pub fn load_from_bytes(bytes: PackedByteArray, model_type: &str) -> Option<Gd<Node>> {
+    let model_type = match ModelType::from_str(model_type) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{e}");
+            return None;
+        }
+    };
+
+    match model_type {
+        // VRM files are glTF files, the extension is handled by the registered godot-vrm
+        // document extensions
+        ModelType::Glb | ModelType::Vrm => load_gltf(bytes),
+        ModelType::PngTuber => load_png(bytes),
+        ModelType::Custom(v) => {
+            error!("Loading custom model type {v} from bytes is not supported");
+            None
+        }
+    }
+}
+
+fn load_gltf(bytes: PackedByteArray) -> Option<Gd<Node>> {
+    let mut doc = GltfDocument::new();
+    let state = GltfState::new();
+
+    // An empty base path means external resources cannot be resolved. This is fine
+    // for glb and vrm files since they are self-contained
+    let err = doc.append_from_buffer(bytes, GodotString::new(), state.clone());
+    if err != Error::OK {
+        error!("Unable to parse glTF data: {err:?}");
+        return None;
+    }
+
+    let r = doc.generate_scene(state);
+    if r.is_none() {
+        error!("Unable to generate scene from glTF data");
+    }
+
+    r
+}
+
+fn load_png(bytes: PackedByteArray) -> Option<Gd<Node>> {
+    let mut image = Image::new();
+
+    let err = image.load_png_from_buffer(bytes);
+    if err != Error::OK {
+        error!("Unable to parse png data: {err:?}");
+        return None;
+    }
+
+    let texture = match ImageTexture::create_from_image(image) {
+        Some(v) => v,
+        None => {
+            error!("Unable to create texture from png data");
+            return None;
+        }
+    };
+
+    let mut sprite = Sprite2D::new_alloc();
+    sprite.set_texture(texture.upcast());
+
+    Some(sprite.upcast())
+}
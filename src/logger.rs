@@ -1,57 +1,250 @@
-use std::io::Write;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Mutex, MutexGuard, Once, TryLockError,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
 
 use godot::{engine::ProjectSettings, prelude::*};
 use log::LevelFilter;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 
 const MAX_LOGS: usize = 128;
-// TODO could use arrayvec
-static mut LOG_STORE: Lazy<Vec<String>> = Lazy::new(|| Vec::with_capacity(MAX_LOGS));
-
-/// Add a `message` to the static `LOG_STORE`.
-///
-/// # Safety
+/// Logs are also flushed on this interval, so that logs are not held back
+/// indefinitely when only a few logs are sent.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
 /// Global access is needed since a Godot autoload might not be available for writing
 /// when the first logger is initialized.
-fn add_to_log_store(message: String) {
-    unsafe {
-        LOG_STORE.push(message);
+static LOG_STORE: Lazy<Mutex<LogStore>> = Lazy::new(|| Mutex::new(LogStore::new()));
+/// Whether flushed logs should also be written next to Godot's own log files.
+static MIRROR_TO_GODOT_LOG: AtomicBool = AtomicBool::new(false);
+static INSTALL_PANIC_HOOK: Once = Once::new();
+static LOG_FLUSHER: Mutex<Option<LogFlusher>> = Mutex::new(None);
+static LOG_PATHS: OnceCell<LogPaths> = OnceCell::new();
+
+/// Log file paths, globalized once so that flushing does not need the engine.
+/// Logs can be flushed from any thread, while [ProjectSettings] must only be
+/// used from the main thread.
+struct LogPaths {
+    log: PathBuf,
+    godot_log: PathBuf,
+}
 
-        if LOG_STORE.len() >= MAX_LOGS.into() {
-            flush_logs();
+impl LogPaths {
+    fn globalize() -> Self {
+        let project_settings = ProjectSettings::singleton();
+
+        Self {
+            log: project_settings
+                .globalize_path(GodotString::from("user://vpuppr.log"))
+                .to_string()
+                .into(),
+            godot_log: project_settings
+                .globalize_path(GodotString::from("user://logs/vpuppr.log"))
+                .to_string()
+                .into(),
         }
     }
 }
 
-// TODO use custom log rotation strategy
+/// Logs waiting to be written to the log file.
+struct LogStore {
+    // TODO could use arrayvec
+    logs: Vec<String>,
+}
+
+impl LogStore {
+    fn new() -> Self {
+        Self {
+            logs: Vec::with_capacity(MAX_LOGS),
+        }
+    }
+}
+
+/// Flushes the static `LOG_STORE` every [FLUSH_INTERVAL] on a background thread.
+/// Dropping the flusher stops the thread and flushes any remaining logs.
+struct LogFlusher {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl LogFlusher {
+    fn spawn() -> std::io::Result<Self> {
+        let (sender, receiver) = mpsc::channel::<()>();
+
+        let handle = std::thread::Builder::new()
+            .name("vpuppr-log-flusher".to_string())
+            .spawn(move || {
+                // Dropping the sender disconnects the channel and stops the thread
+                while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(FLUSH_INTERVAL) {
+                    flush_logs();
+                }
+            })?;
+
+        Ok(Self {
+            stop: Some(sender),
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for LogFlusher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+
+        flush_logs();
+    }
+}
+
+/// Start flushing logs on an interval. Can be called multiple times, only one
+/// flusher runs at a time. Must be called from the main thread, since the log
+/// file paths are resolved here.
+pub fn start_log_flusher() {
+    LOG_PATHS.get_or_init(LogPaths::globalize);
+
+    let mut flusher = LOG_FLUSHER.lock().unwrap_or_else(|e| e.into_inner());
+    if flusher.is_some() {
+        return;
+    }
+
+    match LogFlusher::spawn() {
+        Ok(v) => {
+            flusher.replace(v);
+        }
+        Err(e) => godot_error!("Unable to start flushing logs: {e}"),
+    }
+}
+
+/// Stop flushing logs on an interval and flush all remaining logs. Must be called
+/// before the extension is unloaded, since the flusher thread runs library code.
+pub fn stop_log_flusher() {
+    let flusher = LOG_FLUSHER.lock().unwrap_or_else(|e| e.into_inner()).take();
+    drop(flusher);
+}
+
+/// Lock the static `LOG_STORE`. A poisoned store is still used, since losing logs
+/// is worse than using logs from a thread that panicked.
+fn lock_log_store() -> MutexGuard<'static, LogStore> {
+    LOG_STORE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Add a `message` to the static `LOG_STORE`.
+fn add_to_log_store(message: String) {
+    let mut store = lock_log_store();
+
+    store.logs.push(message);
+
+    if store.logs.len() >= MAX_LOGS {
+        flush_log_store(&mut store);
+    }
+}
+
 /// Flush all logs from the static `LOG_STORE` into a file.
-///
-/// # Safety
-/// Global access is needed for the log store since a Godot autoload might not be available for
-/// writing when the first logger is initialized.
 fn flush_logs() {
-    let project_settings = ProjectSettings::singleton();
+    flush_log_store(&mut lock_log_store());
+}
 
-    let path = project_settings.globalize_path(GodotString::from("user://vpuppr.log"));
+// TODO use custom log rotation strategy
+/// Flush all logs from a locked `store` into a file.
+///
+/// Logs are kept until [start_log_flusher] has resolved the log file paths, only
+/// keeping the latest [MAX_LOGS] logs.
+fn flush_log_store(store: &mut LogStore) {
+    if store.logs.is_empty() {
+        return;
+    }
 
-    let mut opts = std::fs::OpenOptions::new();
-    opts.truncate(false).write(true).create(true);
+    let paths = match LOG_PATHS.get() {
+        Some(v) => v,
+        None => {
+            let excess = store.logs.len().saturating_sub(MAX_LOGS);
+            store.logs.drain(..excess);
+            return;
+        }
+    };
 
-    unsafe {
-        match opts.open(path.to_string()) {
-            Ok(mut file) => {
-                for log in LOG_STORE.iter() {
-                    if let Err(e) = file.write_all(log.as_bytes()) {
-                        godot_error!("{e}");
-                        break;
-                    }
-                }
-            }
-            Err(e) => godot_error!("{e}"),
+    if let Err(e) = write_logs(&paths.log, &store.logs) {
+        godot_error!("{e}");
+    }
+
+    if MIRROR_TO_GODOT_LOG.load(Ordering::Relaxed) {
+        // The logs directory only exists if Godot's file logging is enabled
+        let result = match paths.godot_log.parent() {
+            Some(v) => std::fs::create_dir_all(v),
+            None => Ok(()),
         };
+        if let Err(e) = result.and_then(|_| write_logs(&paths.godot_log, &store.logs)) {
+            godot_error!("{e}");
+        }
+    }
 
-        LOG_STORE.clear();
+    store.logs.clear();
+}
+
+/// Append `logs` to the file at `path`, one log per line, creating the file if needed.
+fn write_logs<P: AsRef<Path>>(path: P, logs: &[String]) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)?;
+
+    for log in logs.iter() {
+        file.write_all(log.as_bytes())?;
+        file.write_all(b"\n")?;
     }
+
+    Ok(())
+}
+
+/// Install a panic hook that adds the panic to the static `LOG_STORE` and flushes
+/// all logs before running the previous hook. This way, the logs leading up to a
+/// crash are not lost.
+///
+/// Can be called multiple times, the hook is only installed once.
+pub fn install_panic_hook() {
+    INSTALL_PANIC_HOOK.call_once(|| {
+        let previous_hook = std::panic::take_hook();
+
+        std::panic::set_hook(Box::new(move |info| {
+            let location = match info.location() {
+                Some(v) => format!("{}:{}:{}", v.file(), v.line(), v.column()),
+                None => "unknown location".to_string(),
+            };
+            let payload = if let Some(v) = info.payload().downcast_ref::<&str>() {
+                v.to_string()
+            } else if let Some(v) = info.payload().downcast_ref::<String>() {
+                v.clone()
+            } else {
+                "unknown panic".to_string()
+            };
+
+            // The panic may have happened while the store was locked on this thread,
+            // in which case locking again would deadlock
+            let store = match LOG_STORE.try_lock() {
+                Ok(v) => Some(v),
+                Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+                Err(TryLockError::WouldBlock) => None,
+            };
+            if let Some(mut store) = store {
+                store.logs.push(insert_metadata(
+                    "Panic".to_string(),
+                    &LogLevel::Error,
+                    format!("{payload} at {location}"),
+                ));
+                flush_log_store(&mut store);
+            }
+
+            previous_hook(info);
+        }));
+    });
 }
 
 /// The level to log outputs at.
@@ -131,6 +324,22 @@ impl Logger {
         self.log(LogLevel::Debug, &mut message.stringify().to_string());
     }
 
+    /// Set whether flushed logs are also written to `user://logs/vpuppr.log`, next to
+    /// Godot's own log files, so that crash reports collecting `user://logs/` include
+    /// them. Disabled by default.
+    #[func]
+    pub fn set_mirror_to_godot_log(enabled: bool) {
+        MIRROR_TO_GODOT_LOG.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Write all buffered logs to the log file. Logs are otherwise only written
+    /// periodically, so this should be called before quitting, e.g. on
+    /// `NOTIFICATION_WM_CLOSE_REQUEST`.
+    #[func]
+    pub fn flush() {
+        flush_logs();
+    }
+
     /// Send a log using an anonymous logger. Logs are printed to stdout.
     #[func(rename = global)]
     pub fn global_bound(source: GodotString, message: Variant) {
@@ -145,6 +354,8 @@ impl Logger {
 impl Logger {
     /// Create a new logger with the given name.
     fn new(name: String) -> Self {
+        start_log_flusher();
+
         Self { name }
     }
 
@@ -220,3 +431,26 @@ where
 
     format!("[{:?}] {} {} {}", level, time, logger_name, message)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_logs_appends() {
+        let path = std::env::temp_dir().join(format!("vpuppr-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        write_logs(&path, &["first".to_string()]).unwrap();
+        write_logs(&path, &["second".to_string(), "third".to_string()]).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            contents.lines().collect::<Vec<_>>(),
+            ["first", "second", "third"]
+        );
+        assert!(contents.ends_with('\n'));
+    }
+}
@@ -4,6 +4,7 @@ pub mod tracking_data;
 use std::{
     fmt::Display,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
 use chrono::{serde::ts_seconds, DateTime, Utc};
@@ -58,6 +59,12 @@ impl std::ops::Deref for GodotPath {
     }
 }
 
+impl From<&str> for GodotPath {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
 impl From<GodotString> for GodotPath {
     fn from(value: GodotString) -> Self {
         Self(value.to_string())
@@ -270,6 +277,8 @@ impl SaveFile for Metadata {
 #[property(name = preview_path, type = GodotString, get = get_preview_path, set = set_preview_path)]
 #[property(name = is_favorite, type = GodotString, get = get_is_favorite, set = set_is_favorite)]
 #[property(name = last_used, type = GodotString, get = get_last_used_int)]
+#[property(name = auto_start_tracker, type = bool, get = get_auto_start_tracker, set = set_auto_start_tracker)]
+#[property(name = tracker, type = GodotString, get = get_tracker)]
 pub struct RunnerData {
     /// The name of the Runner Data. Should generally be set to the name of the model.
     name: String,
@@ -288,6 +297,18 @@ pub struct RunnerData {
     /// The last used time. Used for sorting runners.
     #[serde(with = "ts_seconds")]
     last_used: DateTime<Utc>,
+    /// Saved configuration for 3D puppets.
+    #[serde(default)]
+    puppet_3d: puppet::Puppet3d,
+    /// Saved configuration for VRM puppets.
+    #[serde(default)]
+    vrm_puppet: puppet::VrmPuppet,
+    /// Whether `tracker` should be started when the runner is launched.
+    #[serde(default)]
+    auto_start_tracker: bool,
+    /// The name of the tracker to start when launching. Empty if no tracker is configured.
+    #[serde(default)]
+    tracker: String,
 }
 
 #[godot_api]
@@ -415,6 +436,43 @@ impl RunnerData {
         self.is_favorite = is_favorite;
     }
 
+    #[func]
+    fn get_auto_start_tracker(&self) -> bool {
+        self.auto_start_tracker
+    }
+
+    #[func]
+    fn set_auto_start_tracker(&mut self, auto_start_tracker: bool) {
+        self.auto_start_tracker = auto_start_tracker;
+    }
+
+    #[func]
+    fn get_tracker(&self) -> GodotString {
+        self.tracker.clone().into()
+    }
+
+    /// Set the tracker to start when launching. Uses the same names as the `--tracker`
+    /// cli arg. An empty `tracker` clears the configured tracker.
+    #[func]
+    fn set_tracker(&mut self, tracker: GodotString) -> Error {
+        let tracker = tracker.to_string();
+        if tracker.is_empty() {
+            self.tracker.clear();
+            return Error::OK;
+        }
+
+        match crate::cli::Tracker::from_str(&tracker) {
+            Ok(_) => {
+                self.tracker = tracker;
+                Error::OK
+            }
+            Err(e) => {
+                error!("{e}");
+                Error::ERR_INVALID_PARAMETER
+            }
+        }
+    }
+
     /// Get the last used date as a string.
     #[func]
     fn get_last_used_string(&self) -> GodotString {
@@ -440,6 +498,26 @@ impl RunnerData {
             ..Default::default()
         }
     }
+
+    /// The saved configuration for 3D puppets.
+    pub fn puppet_3d(&self) -> &puppet::Puppet3d {
+        &self.puppet_3d
+    }
+
+    /// Replace the saved configuration for 3D puppets.
+    pub fn set_puppet_3d(&mut self, puppet_3d: puppet::Puppet3d) {
+        self.puppet_3d = puppet_3d;
+    }
+
+    /// The saved configuration for VRM puppets.
+    pub fn vrm_puppet(&self) -> &puppet::VrmPuppet {
+        &self.vrm_puppet
+    }
+
+    /// Replace the saved configuration for VRM puppets.
+    pub fn set_vrm_puppet(&mut self, vrm_puppet: puppet::VrmPuppet) {
+        self.vrm_puppet = vrm_puppet;
+    }
 }
 
 impl SaveFile for RunnerData {
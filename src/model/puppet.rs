@@ -1,25 +1,71 @@
 use std::collections::HashMap;
 
-use godot::prelude::Transform3D;
+use godot::prelude::{Rect2, Transform3D, Vector3};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Puppet3d {
     pub head_bone: String,
     pub head_bone_id: i32,
     pub additional_movement_bones: Vec<i32>,
     pub initial_bone_poses: HashMap<i32, Transform3D>,
+    /// The direction the model faces after import. Zero if no correction is needed.
+    #[serde(default)]
+    pub model_facing: Vector3,
+    /// The screen rect the model is pinned to, if any.
+    #[serde(default)]
+    pub overlay_rect: Option<Rect2>,
+    /// A static position offset for the whole puppet, composed with tracking.
+    #[serde(default)]
+    pub offset_position: Vector3,
+    /// A static rotation offset for the whole puppet in degrees, composed with tracking.
+    #[serde(default)]
+    pub offset_rotation_degrees: Vector3,
+    /// Smoothing factors for incoming tracking data.
+    #[serde(default)]
+    pub smoothing: SmoothingFactors,
+    /// Starting transforms for the ik targets, keyed by target name, e.g. `left_hand`.
+    #[serde(default)]
+    pub ik_target_transforms: HashMap<String, Transform3D>,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// The default smoothing factor for head rotation and position.
+pub const DEFAULT_TRACKING_SMOOTHING: f32 = 0.5;
+
+/// Smoothing factors for each kind of tracking data. `0.0` disables smoothing.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SmoothingFactors {
+    pub head_rotation: f32,
+    pub head_position: f32,
+    pub eye_gaze: f32,
+    pub blend_shapes: f32,
+    #[serde(default)]
+    pub lip_sync: f32,
+}
+
+impl Default for SmoothingFactors {
+    fn default() -> Self {
+        Self {
+            head_rotation: DEFAULT_TRACKING_SMOOTHING,
+            head_position: DEFAULT_TRACKING_SMOOTHING,
+            eye_gaze: 0.0,
+            blend_shapes: 0.0,
+            lip_sync: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct VrmPuppet {
+    /// Blink values above this are snapped to fully closed. `0.0` disables snapping.
     pub blink_threshold: f32,
+    /// Whether both eyes should always blink together.
     pub link_eye_blinks: bool,
     pub use_raw_eye_rotation: bool,
     pub vrm_type: VrmType,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 pub enum VrmType {
     #[default]
     Base,
@@ -5,16 +5,45 @@ use log::error;
 use serde::{Deserialize, Serialize};
 
 use super::GodotPath;
+use crate::data_parser::normalize_blend_shape_name;
 
-#[derive(Debug, Default, GodotClass, Serialize, Deserialize)]
+// Default tracker options. These are the only place tracker defaults should be defined.
+
+/// The default address for trackers that send data over the network.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1";
+pub const DEFAULT_I_FACIAL_MOCAP_PORT: i32 = 49983;
+pub const DEFAULT_VTUBE_STUDIO_PORT: i32 = 21412;
+pub const DEFAULT_MEOW_FACE_PORT: i32 = 21412;
+/// OpenSeeFace sends to this port unless configured otherwise.
+pub const DEFAULT_OPEN_SEE_FACE_PORT: i32 = 11573;
+/// The default camera resolution for MediaPipe, as `(width, height)`.
+pub const DEFAULT_MEDIA_PIPE_CAMERA_RESOLUTION: (i32, i32) = (640, 480);
+
+#[derive(Debug, GodotClass, Serialize, Deserialize)]
 #[class(init)]
 pub struct IFacialMocapOptions {
     pub address: GodotPath,
     pub port: i32,
 }
 
+impl Default for IFacialMocapOptions {
+    fn default() -> Self {
+        Self {
+            address: DEFAULT_ADDRESS.into(),
+            port: DEFAULT_I_FACIAL_MOCAP_PORT,
+        }
+    }
+}
+
 #[godot_api]
-impl IFacialMocapOptions {}
+impl IFacialMocapOptions {
+    /// Restore the default options. Changes are persisted once the owning
+    /// save file is saved.
+    #[func]
+    pub fn reset_to_defaults(&mut self) {
+        *self = Self::default();
+    }
+}
 
 #[derive(Debug, Default, GodotClass)]
 pub struct IFacialMocapData {
@@ -35,95 +64,9 @@ impl RefCountedVirtual for IFacialMocapData {
 #[godot_api]
 impl IFacialMocapData {
     #[func]
-    fn from(data: PackedByteArray) -> Gd<IFacialMocapData> {
+    pub fn from(data: PackedByteArray) -> Gd<IFacialMocapData> {
         Gd::new(match std::str::from_utf8(data.as_slice()) {
-            Ok(v) => {
-                let mut r = Self::default();
-
-                let mut split = v.split("|");
-                while let Some(v) = split.next() {
-                    if let Some((k, v)) = v.split_once('#') {
-                        // TODO these are all gross, there must be a better way
-                        match k {
-                            "=head" => {
-                                let vals = v.splitn(5, ',').collect::<Vec<&str>>();
-
-                                r.rotation.x = vals
-                                    .get(0)
-                                    .map(|v| v.parse::<f32>().unwrap_or_default())
-                                    .unwrap_or_default();
-                                r.rotation.y = vals
-                                    .get(1)
-                                    .map(|v| v.parse::<f32>().unwrap_or_default())
-                                    .unwrap_or_default();
-                                r.rotation.z = vals
-                                    .get(2)
-                                    .map(|v| v.parse::<f32>().unwrap_or_default())
-                                    .unwrap_or_default();
-
-                                r.position.x = vals
-                                    .get(3)
-                                    .map(|v| v.parse::<f32>().unwrap_or_default())
-                                    .unwrap_or_default();
-                                r.position.y = vals
-                                    .get(4)
-                                    .map(|v| v.parse::<f32>().unwrap_or_default())
-                                    .unwrap_or_default();
-                                r.position.z = vals
-                                    .get(5)
-                                    .map(|v| v.parse::<f32>().unwrap_or_default())
-                                    .unwrap_or_default();
-                            }
-                            "rightEye" => {
-                                let vals = v.splitn(2, ',').collect::<Vec<&str>>();
-
-                                r.right_eye.x = vals
-                                    .get(0)
-                                    .map(|v| v.parse::<f32>().unwrap_or_default())
-                                    .unwrap_or_default();
-                                r.right_eye.y = vals
-                                    .get(1)
-                                    .map(|v| v.parse::<f32>().unwrap_or_default())
-                                    .unwrap_or_default();
-                                r.right_eye.z = vals
-                                    .get(2)
-                                    .map(|v| v.parse::<f32>().unwrap_or_default())
-                                    .unwrap_or_default();
-                            }
-                            "leftEye" => {
-                                let vals = v.splitn(2, ',').collect::<Vec<&str>>();
-
-                                r.left_eye.x = vals
-                                    .get(0)
-                                    .map(|v| v.parse::<f32>().unwrap_or_default())
-                                    .unwrap_or_default();
-                                r.left_eye.y = vals
-                                    .get(1)
-                                    .map(|v| v.parse::<f32>().unwrap_or_default())
-                                    .unwrap_or_default();
-                                r.left_eye.z = vals
-                                    .get(2)
-                                    .map(|v| v.parse::<f32>().unwrap_or_default())
-                                    .unwrap_or_default();
-                            }
-                            _ => error!("Unhandled ifm data key: {k}"),
-                        }
-                    } else if let Some((k, v)) = v.split_once("-") {
-                        r.blend_shapes.insert(
-                            k
-                                // TODO maybe use https://github.com/BurntSushi/aho-corasick for faster replace?
-                                .replace("_L", "left")
-                                .replace("_R", "right"),
-                            f32::from(v.parse::<i16>().unwrap_or(0)) / 100.0,
-                        );
-                    } else if v.is_empty() {
-                    } else {
-                        error!("Unhandled ifm key-value pair {v}");
-                    }
-                }
-
-                r
-            }
+            Ok(v) => Self::parse(v),
             Err(e) => {
                 error!("{e}");
                 Self::default()
@@ -132,15 +75,130 @@ impl IFacialMocapData {
     }
 }
 
-#[derive(Debug, Default, GodotClass, Serialize, Deserialize)]
+impl IFacialMocapData {
+    /// Parse a decoded iFacialMocap packet. Blend shape weights are sent as
+    /// integers from 0-100 and are scaled to 0.0-1.0.
+    pub fn parse(data: &str) -> Self {
+        let mut r = Self::default();
+
+        // Packets end with a trailing bar, so the last segment is always empty
+        for v in data.split('|').filter(|v| !v.is_empty()) {
+            if let Some((k, v)) = v.split_once('#') {
+                // TODO these are all gross, there must be a better way
+                match k {
+                    "=head" => {
+                        let vals = v.split(',').collect::<Vec<&str>>();
+
+                        r.rotation.x = vals
+                            .get(0)
+                            .map(|v| v.parse::<f32>().unwrap_or_default())
+                            .unwrap_or_default();
+                        r.rotation.y = vals
+                            .get(1)
+                            .map(|v| v.parse::<f32>().unwrap_or_default())
+                            .unwrap_or_default();
+                        r.rotation.z = vals
+                            .get(2)
+                            .map(|v| v.parse::<f32>().unwrap_or_default())
+                            .unwrap_or_default();
+
+                        r.position.x = vals
+                            .get(3)
+                            .map(|v| v.parse::<f32>().unwrap_or_default())
+                            .unwrap_or_default();
+                        r.position.y = vals
+                            .get(4)
+                            .map(|v| v.parse::<f32>().unwrap_or_default())
+                            .unwrap_or_default();
+                        r.position.z = vals
+                            .get(5)
+                            .map(|v| v.parse::<f32>().unwrap_or_default())
+                            .unwrap_or_default();
+                    }
+                    "rightEye" => {
+                        let vals = v.split(',').collect::<Vec<&str>>();
+
+                        r.right_eye.x = vals
+                            .get(0)
+                            .map(|v| v.parse::<f32>().unwrap_or_default())
+                            .unwrap_or_default();
+                        r.right_eye.y = vals
+                            .get(1)
+                            .map(|v| v.parse::<f32>().unwrap_or_default())
+                            .unwrap_or_default();
+                        r.right_eye.z = vals
+                            .get(2)
+                            .map(|v| v.parse::<f32>().unwrap_or_default())
+                            .unwrap_or_default();
+                    }
+                    "leftEye" => {
+                        let vals = v.split(',').collect::<Vec<&str>>();
+
+                        r.left_eye.x = vals
+                            .get(0)
+                            .map(|v| v.parse::<f32>().unwrap_or_default())
+                            .unwrap_or_default();
+                        r.left_eye.y = vals
+                            .get(1)
+                            .map(|v| v.parse::<f32>().unwrap_or_default())
+                            .unwrap_or_default();
+                        r.left_eye.z = vals
+                            .get(2)
+                            .map(|v| v.parse::<f32>().unwrap_or_default())
+                            .unwrap_or_default();
+                    }
+                    _ => error!("Unhandled ifm data key: {k}"),
+                }
+            } else if let Some((k, v)) = split_blend_shape(v) {
+                r.blend_shapes
+                    .insert(normalize_blend_shape_name(k), f32::from(v) / 100.0);
+            } else {
+                error!("Unhandled ifm key-value pair {v}");
+            }
+        }
+
+        r
+    }
+}
+
+/// Split an iFacialMocap blend shape segment like `jawOpen-45` into its name and
+/// weight. The last `-` is the delimiter, so a negative weight shows up as a
+/// second `-` at the end of the name, e.g. `eyeLookOut_L--5`.
+fn split_blend_shape(segment: &str) -> Option<(&str, i16)> {
+    let (k, v) = segment.rsplit_once('-')?;
+    let v = v.parse::<i16>().unwrap_or(0);
+
+    Some(match k.strip_suffix('-') {
+        Some(k) => (k, -v),
+        None => (k, v),
+    })
+}
+
+#[derive(Debug, GodotClass, Serialize, Deserialize)]
 #[class(init)]
 pub struct VTubeStudioOptions {
     pub address: GodotPath,
     pub port: i32,
 }
 
+impl Default for VTubeStudioOptions {
+    fn default() -> Self {
+        Self {
+            address: DEFAULT_ADDRESS.into(),
+            port: DEFAULT_VTUBE_STUDIO_PORT,
+        }
+    }
+}
+
 #[godot_api]
-impl VTubeStudioOptions {}
+impl VTubeStudioOptions {
+    /// Restore the default options. Changes are persisted once the owning
+    /// save file is saved.
+    #[func]
+    pub fn reset_to_defaults(&mut self) {
+        *self = Self::default();
+    }
+}
 
 #[derive(Debug, Default, Serialize, Deserialize, GodotClass)]
 pub struct VTubeStudioData {
@@ -154,6 +212,8 @@ pub struct VTubeStudioData {
     pub eye_right: Option<Vector3>,
     #[serde(rename = "BlendShapes")]
     pub blend_shapes: Option<Vec<VtBlendShape>>,
+    #[serde(rename = "FaceFound")]
+    pub face_found: Option<bool>,
 }
 
 #[godot_api]
@@ -166,7 +226,7 @@ impl RefCountedVirtual for VTubeStudioData {
 #[godot_api]
 impl VTubeStudioData {
     #[func]
-    fn from(data: PackedByteArray) -> Gd<VTubeStudioData> {
+    pub fn from(data: PackedByteArray) -> Gd<VTubeStudioData> {
         Gd::new(
             match serde_json::from_slice::<VTubeStudioData>(data.as_slice()) {
                 Ok(v) => v,
@@ -185,21 +245,238 @@ pub struct VtBlendShape {
     pub v: f32,
 }
 
-#[derive(Debug, Default, GodotClass, Serialize, Deserialize)]
+#[derive(Debug, GodotClass, Serialize, Deserialize)]
 #[class(init)]
 pub struct MeowFaceOptions {
     pub address: GodotPath,
     pub port: i32,
 }
 
+impl Default for MeowFaceOptions {
+    fn default() -> Self {
+        Self {
+            address: DEFAULT_ADDRESS.into(),
+            port: DEFAULT_MEOW_FACE_PORT,
+        }
+    }
+}
+
 #[godot_api]
-impl MeowFaceOptions {}
+impl MeowFaceOptions {
+    /// Restore the default options. Changes are persisted once the owning
+    /// save file is saved.
+    #[func]
+    pub fn reset_to_defaults(&mut self) {
+        *self = Self::default();
+    }
+}
 
-#[derive(Debug, Default, GodotClass, Serialize, Deserialize)]
+#[derive(Debug, GodotClass, Serialize, Deserialize)]
 #[class(init)]
 pub struct MediaPipeOptions {
     pub camera_resolution: Vector2i,
 }
 
+impl Default for MediaPipeOptions {
+    fn default() -> Self {
+        let (width, height) = DEFAULT_MEDIA_PIPE_CAMERA_RESOLUTION;
+
+        Self {
+            camera_resolution: Vector2i::new(width, height),
+        }
+    }
+}
+
 #[godot_api]
-impl MediaPipeOptions {}
+impl MediaPipeOptions {
+    /// Restore the default options. Changes are persisted once the owning
+    /// save file is saved.
+    #[func]
+    pub fn reset_to_defaults(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Head and blend shape data from MediaPipe. Godot is not able to send GDMP types
+/// over the wire, so the data is converted once from the raw values.
+#[derive(Debug, GodotClass)]
+pub struct MediaPipeData {
+    /// The facial transformation matrix.
+    pub projection: Projection,
+    /// Blend shape weights keyed by lowercase name.
+    pub blend_shapes: HashMap<String, f32>,
+    /// Pose world landmarks. Empty if only the face is tracked.
+    pub pose_landmarks: Vec<Vector3>,
+    /// Hand world landmarks for the subject's left hand. Empty if the hand is not tracked.
+    pub left_hand_landmarks: Vec<Vector3>,
+    /// Hand world landmarks for the subject's right hand. Empty if the hand is not tracked.
+    pub right_hand_landmarks: Vec<Vector3>,
+}
+
+#[godot_api]
+impl RefCountedVirtual for MediaPipeData {
+    fn init(_base: godot::obj::Base<Self::Base>) -> Self {
+        Self::new(Projection::IDENTITY, [])
+    }
+}
+
+#[godot_api]
+impl MediaPipeData {
+    /// Convert raw MediaPipe data. `blend_shapes` is a mapping of blend shape names
+    /// to weights, entries that are not numbers are skipped.
+    #[func]
+    fn from(projection: Projection, blend_shapes: Dictionary) -> Gd<MediaPipeData> {
+        Gd::new(Self::new(
+            projection,
+            blend_shapes
+                .iter_shared()
+                .filter_map(|(k, v)| match v.try_to::<f32>() {
+                    Ok(v) => Some((k.to_string(), v)),
+                    Err(_) => {
+                        error!("Invalid MediaPipe blend shape value for {k}: {v}");
+                        None
+                    }
+                }),
+        ))
+    }
+
+    /// Set the world landmarks from MediaPipe pose and hand tracking. Any of these
+    /// may be empty, in which case that part of the body is not tracked.
+    #[func]
+    fn set_landmarks(
+        &mut self,
+        pose: PackedVector3Array,
+        left_hand: PackedVector3Array,
+        right_hand: PackedVector3Array,
+    ) {
+        self.pose_landmarks = pose.as_slice().to_vec();
+        self.left_hand_landmarks = left_hand.as_slice().to_vec();
+        self.right_hand_landmarks = right_hand.as_slice().to_vec();
+    }
+}
+
+impl MediaPipeData {
+    pub fn new<I>(projection: Projection, blend_shapes: I) -> Self
+    where
+        I: IntoIterator<Item = (String, f32)>,
+    {
+        Self {
+            projection,
+            blend_shapes: blend_shapes
+                .into_iter()
+                .map(|(k, v)| (k.to_lowercase(), v))
+                .collect(),
+            pose_landmarks: vec![],
+            left_hand_landmarks: vec![],
+            right_hand_landmarks: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn media_pipe_blend_shapes_are_lowercase() {
+        let data = MediaPipeData::new(
+            Projection::IDENTITY,
+            [
+                ("jawOpen".to_string(), 0.5),
+                ("EyeBlinkLeft".to_string(), 1.0),
+            ],
+        );
+
+        assert_eq!(data.blend_shapes.len(), 2);
+        assert_eq!(data.blend_shapes["jawopen"], 0.5);
+        assert_eq!(data.blend_shapes["eyeblinkleft"], 1.0);
+    }
+
+    #[test]
+    fn i_facial_mocap_blend_shapes_are_scaled() {
+        let data = IFacialMocapData::parse("mouthSmile_L-0|jawOpen-45|eyeBlink_R-100|");
+
+        assert_eq!(data.blend_shapes.len(), 3);
+        assert_eq!(data.blend_shapes["mouthSmileleft"], 0.0);
+        assert_eq!(data.blend_shapes["jawOpen"], 0.45);
+        assert_eq!(data.blend_shapes["eyeBlinkright"], 1.0);
+    }
+
+    #[test]
+    fn i_facial_mocap_sample_packet() {
+        let data = IFacialMocapData::parse(concat!(
+            "mouthSmile_R-0|eyeLookOut_L-0|mouthUpperUp_L-11|eyeWide_R-0|mouthClose-8|",
+            "jawOpen-2|eyeLookIn_R--3|",
+            "=head#-21.488958,-6.038993,-6.6019735,-0.030653415,-0.10287084,-0.6584072|",
+            "rightEye#6.0297494,2.4403017,0.25649446|leftEye#6.034903,-1.6660284,-0.17520553|"
+        ));
+
+        assert_eq!(data.blend_shapes.len(), 7);
+        assert_eq!(data.blend_shapes["eyeLookOutleft"], 0.0);
+        assert_eq!(data.blend_shapes["mouthUpperUpleft"], 0.11);
+        assert_eq!(data.blend_shapes["eyeLookInright"], -0.03);
+
+        assert_eq!(
+            data.rotation,
+            Vector3::new(-21.488958, -6.038993, -6.6019735)
+        );
+        assert_eq!(
+            data.position,
+            Vector3::new(-0.030653415, -0.10287084, -0.6584072)
+        );
+        assert_eq!(
+            data.right_eye,
+            Vector3::new(6.0297494, 2.4403017, 0.25649446)
+        );
+        assert_eq!(
+            data.left_eye,
+            Vector3::new(6.034903, -1.6660284, -0.17520553)
+        );
+    }
+
+    #[test]
+    fn i_facial_mocap_skips_empty_segments() {
+        let data = IFacialMocapData::parse("jawOpen-45||||");
+
+        assert_eq!(data.blend_shapes.len(), 1);
+        assert_eq!(data.blend_shapes["jawOpen"], 0.45);
+    }
+
+    #[test]
+    fn network_defaults_match_table() {
+        let mut ifm = IFacialMocapOptions {
+            address: "192.168.1.2".into(),
+            port: 1,
+        };
+        ifm.reset_to_defaults();
+        assert_eq!(ifm.address.as_str(), DEFAULT_ADDRESS);
+        assert_eq!(ifm.port, DEFAULT_I_FACIAL_MOCAP_PORT);
+
+        let mut vts = VTubeStudioOptions {
+            address: "192.168.1.2".into(),
+            port: 1,
+        };
+        vts.reset_to_defaults();
+        assert_eq!(vts.address.as_str(), DEFAULT_ADDRESS);
+        assert_eq!(vts.port, DEFAULT_VTUBE_STUDIO_PORT);
+
+        let mut meow_face = MeowFaceOptions {
+            address: "192.168.1.2".into(),
+            port: 1,
+        };
+        meow_face.reset_to_defaults();
+        assert_eq!(meow_face.address.as_str(), DEFAULT_ADDRESS);
+        assert_eq!(meow_face.port, DEFAULT_MEOW_FACE_PORT);
+    }
+
+    #[test]
+    fn media_pipe_defaults_match_table() {
+        let mut options = MediaPipeOptions {
+            camera_resolution: Vector2i::new(1, 1),
+        };
+        options.reset_to_defaults();
+
+        let (width, height) = DEFAULT_MEDIA_PIPE_CAMERA_RESOLUTION;
+        assert_eq!(options.camera_resolution, Vector2i::new(width, height));
+    }
+}
@@ -2,16 +2,23 @@ pub mod glb_puppet;
 pub mod png_puppet;
 pub mod vrm_puppet;
 
-use std::fmt::Display;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 
 use godot::{
-    engine::{MeshInstance3D, Skeleton3D},
+    engine::{ArrayMesh, FileAccess, MeshInstance3D, Skeleton3D},
     prelude::*,
 };
+use log::{debug, error};
 
 use crate::{
-    gstring,
-    model::tracking_data::{IFacialMocapData, VTubeStudioData},
+    cli::Tracker,
+    model::{
+        puppet::SmoothingFactors,
+        tracking_data::{IFacialMocapData, MediaPipeData, VTubeStudioData},
+    },
     Logger,
 };
 
@@ -33,6 +40,8 @@ pub struct IkTargets3d {
     #[var]
     pub hips: Option<Gd<Node3D>>,
     #[var]
+    pub hips_starting_transform: Transform3D,
+    #[var]
     pub left_foot: Option<Gd<Node3D>>,
     #[var]
     pub right_foot: Option<Gd<Node3D>>,
@@ -41,16 +50,89 @@ pub struct IkTargets3d {
 #[godot_api]
 impl IkTargets3d {}
 
+impl IkTargets3d {
+    /// Move the target `name`, e.g. `left_hand`, to `transform` and use it as the
+    /// target's starting transform. Returns `false` if there is no such target.
+    pub fn set_target_transform(&mut self, name: &str, transform: Transform3D) -> bool {
+        let (target, starting_transform) = match name {
+            "head" => (&mut self.head, Some(&mut self.head_starting_transform)),
+            "left_hand" => (
+                &mut self.left_hand,
+                Some(&mut self.left_hand_starting_transform),
+            ),
+            "right_hand" => (
+                &mut self.right_hand,
+                Some(&mut self.right_hand_starting_transform),
+            ),
+            "hips" => (&mut self.hips, Some(&mut self.hips_starting_transform)),
+            "left_foot" => (&mut self.left_foot, None),
+            "right_foot" => (&mut self.right_foot, None),
+            _ => return false,
+        };
+
+        if let Some(v) = starting_transform {
+            *v = transform;
+        }
+        if let Some(v) = target.as_mut() {
+            v.set_transform(transform);
+        }
+
+        true
+    }
+}
+
 pub trait Puppet {
     fn logger(&self) -> Logger;
-    fn managed_node(&self) -> Gd<Node>;
+
+    /// The model managed by the puppet, or `None` if no model was added yet.
+    fn try_managed_node(&self) -> Option<Gd<Node>>;
+
+    /// The model managed by the puppet. Panics if no model was added yet, use
+    /// [Puppet::try_managed_node] where that is a valid state.
+    fn managed_node(&self) -> Gd<Node> {
+        match self.try_managed_node() {
+            Some(v) => v,
+            None => {
+                self.logger()
+                    .error("Unable to get managed node, this is a major error!");
+
+                panic!("Bailing out!");
+            }
+        }
+    }
+
+    /// Capture the last rendered frame of the viewport containing the puppet as a png.
+    ///
+    /// # Returns
+    /// The png data or an empty [PackedByteArray] if nothing was rendered yet.
+    fn capture_frame(&self) -> PackedByteArray {
+        let logger = self.logger();
+
+        let image = match self
+            .try_managed_node()
+            .and_then(|v| v.get_viewport())
+            .and_then(|v| v.get_texture())
+            .and_then(|v| v.get_image())
+        {
+            Some(v) if !v.is_empty() => v,
+            _ => {
+                logger.error("No frame rendered yet, unable to capture frame");
+                return PackedByteArray::new();
+            }
+        };
+
+        image.save_png_to_buffer()
+    }
 }
 
 pub const SKELETON_NODE_NAME_3D: &str = "*Skeleton*";
+const MESH_INST_3D: &str = "MeshInstance3D";
+/// The direction models are expected to face. Matches Godot's `Vector3.MODEL_FRONT`.
+pub const MODEL_FRONT: Vector3 = Vector3::BACK;
 pub trait Puppet3d: Puppet {
     fn find_skeleton(&self, base: &Base<Node3D>) -> Option<Gd<Skeleton3D>> {
         if let Some(v) = base
-            .find_child_ex(gstring!(SKELETON_NODE_NAME_3D))
+            .find_child_ex(SKELETON_NODE_NAME_3D.into())
             .owned(false)
             .done()
         {
@@ -65,14 +147,97 @@ pub trait Puppet3d: Puppet {
         self.managed_node().get_node_or_null(node_path)
     }
 
+    /// Rotate the managed node so that a model facing `forward` ends up facing
+    /// [MODEL_FRONT]. Tracking is applied to bones, so it composes with the correction.
+    ///
+    /// Does nothing if no model was added yet, puppets apply the correction again
+    /// once the model is loaded.
+    fn apply_model_facing(&self, forward: Vector3) {
+        let node = match self.try_managed_node() {
+            Some(v) => v,
+            None => {
+                self.logger()
+                    .debug("No model found, model facing will be applied once loaded");
+                return;
+            }
+        };
+
+        match node.try_cast::<Node3D>() {
+            Some(mut v) => v.set_quaternion(facing_correction(forward)),
+            None => self
+                .logger()
+                .error("Managed node is not a Node3D, unable to correct model facing"),
+        }
+    }
+
+    /// Position and scale the managed node so that a model `height` units tall, with
+    /// its origin at its feet, fills `screen_rect` as seen by the current camera.
+    fn apply_overlay_transform(&self, screen_rect: Rect2, height: f32) {
+        let logger = self.logger();
+
+        if height <= 0.0 {
+            logger.error(format!(
+                "Invalid model height {height}, unable to pin model"
+            ));
+            return;
+        }
+
+        let mut node = match self.managed_node().try_cast::<Node3D>() {
+            Some(v) => v,
+            None => {
+                logger.error("Managed node is not a Node3D, unable to pin model");
+                return;
+            }
+        };
+        let camera = match node.get_viewport().and_then(|v| v.get_camera_3d()) {
+            Some(v) => v,
+            None => {
+                logger.error("No camera found, unable to pin model");
+                return;
+            }
+        };
+
+        let depth = camera
+            .get_global_position()
+            .distance_to(node.get_global_position());
+        let center_x = screen_rect.position.x + (screen_rect.size.x / 2.0);
+        let top = camera.project_position(Vector2::new(center_x, screen_rect.position.y), depth);
+        let bottom = camera.project_position(
+            Vector2::new(center_x, screen_rect.position.y + screen_rect.size.y),
+            depth,
+        );
+
+        node.set_global_position(bottom);
+        node.set_scale(Vector3::ONE * (top.distance_to(bottom) / height));
+    }
+
+    /// Log a suggestion if the model looks like it was imported facing backwards.
+    ///
+    /// A model facing [MODEL_FRONT] has its left hand on the positive x axis.
+    fn check_model_facing(&self, skeleton: &Gd<Skeleton3D>) {
+        let bone_idx = skeleton.find_bone("LeftHand".into());
+        if bone_idx < 0 {
+            return;
+        }
+
+        if skeleton.get_bone_global_pose(bone_idx).origin.x < 0.0 {
+            self.logger().warn(
+                "Model appears to be facing backwards, consider calling set_model_facing(Vector3(0, 0, -1))",
+            );
+        }
+    }
+
     fn handle_i_facial_mocap(&mut self, data: Gd<IFacialMocapData>);
 
     fn handle_vtube_studio(&mut self, data: Gd<VTubeStudioData>);
 
     fn handle_meow_face(&mut self, data: Gd<VTubeStudioData>);
 
-    // TODO you-win Sept 10, 2023: Godot is not able to send GDMP types over the wire
-    fn handle_media_pipe(&mut self, projection: Projection, blend_shapes: Dictionary);
+    fn handle_media_pipe(&mut self, data: Gd<MediaPipeData>);
+
+    /// Handle a frame from the OpenSeeFace receiver. See [crate::data_parser::DataParser::open_see_face]
+    /// for the keys in `data`.
+    fn handle_open_see_face(&mut self, data: Dictionary);
 }
 
 /// Contains data necessary for manipulating blend shapes. Meant to be viewable by a user.
@@ -84,6 +249,12 @@ pub struct BlendShapeMapping {
     blend_shape_path: String,
     /// The value of the blend shape, generally from 0.0-1.0. Is modified in place.
     value: f32,
+    /// The prebuilt [NodePath] for `blend_shape_path`, so that it does not need to be
+    /// rebuilt every frame.
+    node_path: Option<NodePath>,
+    /// The mesh resolved from `mesh_id`, so that it does not need to be looked up
+    /// every frame. Only valid on the thread that owns the mesh.
+    mesh: Option<Gd<MeshInstance3D>>,
 }
 
 impl BlendShapeMapping {
@@ -92,8 +263,987 @@ impl BlendShapeMapping {
             mesh_id,
             blend_shape_path,
             value,
+            node_path: None,
+            mesh: None,
+        }
+    }
+
+    /// Build and store the [NodePath] for the blend shape and resolve its mesh.
+    /// Should be called once while setting up.
+    pub fn cache(&mut self) {
+        self.node_path = Some(NodePath::from(self.blend_shape_path.as_str()));
+        self.mesh = Gd::try_from_instance_id(InstanceId::from_i64(self.mesh_id));
+        if self.mesh.is_none() {
+            error!(
+                "Unable to find mesh {} for {}",
+                self.mesh_id, self.blend_shape_path
+            );
+        }
+    }
+
+    /// Store `value` and apply it to the mesh. Must be called from the thread that
+    /// owns the mesh.
+    pub fn set_value(&mut self, value: f32) {
+        self.value = value;
+
+        let node_path = self.node_path();
+        let mesh = match self.mesh.as_mut() {
+            Some(v) => v,
+            None => return,
+        };
+        mesh.set_indexed(node_path, value.to_variant());
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// Convert the mapping to a [Dictionary] for diagnostics.
+    pub fn to_dict(&self) -> Dictionary {
+        let mut r = Dictionary::new();
+
+        r.insert("mesh_id", self.mesh_id);
+        r.insert(
+            "blend_shape_path",
+            GodotString::from(&self.blend_shape_path),
+        );
+        r.insert("value", self.value);
+
+        r
+    }
+
+    /// Get the [NodePath] for the blend shape, only building it if it was not cached.
+    pub fn node_path(&self) -> NodePath {
+        match &self.node_path {
+            Some(v) => v.clone(),
+            None => NodePath::from(self.blend_shape_path.as_str()),
+        }
+    }
+}
+
+/// The pose of a puppet as of the last applied tracking frame.
+#[derive(Debug, Default)]
+pub struct AppliedPose {
+    pub head_rotation: Vector3,
+    pub head_position: Vector3,
+    pub left_eye: Vector3,
+    pub right_eye: Vector3,
+}
+
+impl AppliedPose {
+    /// Convert the pose and all non-zero blend shape values to a [Dictionary].
+    pub fn to_dict(&self, blend_shape_mappings: &HashMap<String, BlendShapeMapping>) -> Dictionary {
+        let mut r = Dictionary::new();
+
+        r.insert("head_rotation", self.head_rotation);
+        r.insert("head_position", self.head_position);
+        r.insert("left_eye", self.left_eye);
+        r.insert("right_eye", self.right_eye);
+        r.insert(
+            "blend_shapes",
+            blend_shape_mappings
+                .iter()
+                .filter(|(_, v)| v.value != 0.0)
+                .map(|(k, v)| (GodotString::from(k), v.value))
+                .collect::<Dictionary>(),
+        );
+
+        r
+    }
+}
+
+/// The kinds of tracking data that can be smoothed independently of each other.
+#[repr(i64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmoothingKind {
+    HeadRotation = 0,
+    HeadPosition = 1,
+    EyeGaze = 2,
+    BlendShapes = 3,
+    LipSync = 4,
+}
+
+impl TryFrom<i64> for SmoothingKind {
+    type Error = i64;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::HeadRotation),
+            1 => Ok(Self::HeadPosition),
+            2 => Ok(Self::EyeGaze),
+            3 => Ok(Self::BlendShapes),
+            4 => Ok(Self::LipSync),
+            _ => Err(value),
+        }
+    }
+}
+
+/// Exponential smoothing for incoming tracking data. Each [SmoothingKind] has its
+/// own factor and keeps its own previous values.
+///
+/// A factor of `0.0` applies incoming values directly, while factors closer to
+/// `1.0` smooth more heavily.
+#[derive(Debug, Default)]
+pub struct Smoothing {
+    factors: [f32; 5],
+    /// Apply incoming values directly, regardless of the configured factors.
+    pub bypass: bool,
+
+    head_rotation: Option<Vector3>,
+    head_position: Option<Vector3>,
+    left_eye: Option<Vector3>,
+    right_eye: Option<Vector3>,
+    blend_shapes: HashMap<String, f32>,
+    lip_sync: Option<f32>,
+}
+
+impl From<SmoothingFactors> for Smoothing {
+    fn from(value: SmoothingFactors) -> Self {
+        let mut r = Self::default();
+        r.set_factors(value);
+
+        r
+    }
+}
+
+impl Smoothing {
+    /// Forget all previous values while keeping the configured factors.
+    pub fn reset(&mut self) {
+        self.head_rotation = None;
+        self.head_position = None;
+        self.left_eye = None;
+        self.right_eye = None;
+        self.blend_shapes.clear();
+        self.lip_sync = None;
+    }
+
+    /// Set the factors for every kind at once.
+    pub fn set_factors(&mut self, factors: SmoothingFactors) {
+        self.set_factor(SmoothingKind::HeadRotation, factors.head_rotation);
+        self.set_factor(SmoothingKind::HeadPosition, factors.head_position);
+        self.set_factor(SmoothingKind::EyeGaze, factors.eye_gaze);
+        self.set_factor(SmoothingKind::BlendShapes, factors.blend_shapes);
+        self.set_factor(SmoothingKind::LipSync, factors.lip_sync);
+    }
+
+    pub fn set_factor(&mut self, kind: SmoothingKind, factor: f32) {
+        self.factors[kind as usize] = factor.clamp(0.0, 1.0);
+    }
+
+    /// The configured factors for every kind, e.g. for saving them. Not affected
+    /// by `bypass`.
+    pub fn factors(&self) -> SmoothingFactors {
+        SmoothingFactors {
+            head_rotation: self.factors[SmoothingKind::HeadRotation as usize],
+            head_position: self.factors[SmoothingKind::HeadPosition as usize],
+            eye_gaze: self.factors[SmoothingKind::EyeGaze as usize],
+            blend_shapes: self.factors[SmoothingKind::BlendShapes as usize],
+            lip_sync: self.factors[SmoothingKind::LipSync as usize],
+        }
+    }
+
+    pub fn factor(&self, kind: SmoothingKind) -> f32 {
+        if self.bypass {
+            0.0
+        } else {
+            self.factors[kind as usize]
+        }
+    }
+
+    pub fn head_rotation(&mut self, value: Vector3) -> Vector3 {
+        let factor = self.factor(SmoothingKind::HeadRotation);
+        smooth_vector(&mut self.head_rotation, value, factor)
+    }
+
+    pub fn head_position(&mut self, value: Vector3) -> Vector3 {
+        let factor = self.factor(SmoothingKind::HeadPosition);
+        smooth_vector(&mut self.head_position, value, factor)
+    }
+
+    pub fn left_eye(&mut self, value: Vector3) -> Vector3 {
+        let factor = self.factor(SmoothingKind::EyeGaze);
+        smooth_vector(&mut self.left_eye, value, factor)
+    }
+
+    pub fn right_eye(&mut self, value: Vector3) -> Vector3 {
+        let factor = self.factor(SmoothingKind::EyeGaze);
+        smooth_vector(&mut self.right_eye, value, factor)
+    }
+
+    pub fn lip_sync(&mut self, value: f32) -> f32 {
+        let factor = self.factor(SmoothingKind::LipSync);
+        let r = match self.lip_sync {
+            Some(v) => v + (value - v) * (1.0 - factor),
+            None => value,
+        };
+        self.lip_sync.replace(r);
+
+        r
+    }
+
+    pub fn blend_shape(&mut self, name: &str, value: f32) -> f32 {
+        let factor = self.factor(SmoothingKind::BlendShapes);
+        match self.blend_shapes.get_mut(name) {
+            Some(previous) => {
+                *previous += (value - *previous) * (1.0 - factor);
+                *previous
+            }
+            None => {
+                self.blend_shapes.insert(name.to_string(), value);
+                value
+            }
+        }
+    }
+}
+
+/// Move `previous` towards `value` by `factor` and return the result. The first
+/// value is always applied directly.
+fn smooth_vector(previous: &mut Option<Vector3>, value: Vector3, factor: f32) -> Vector3 {
+    let r = match previous {
+        Some(v) => *v + (value - *v) * (1.0 - factor),
+        None => value,
+    };
+    previous.replace(r);
+
+    r
+}
+
+/// Eye rotations are clamped to this many degrees on each axis. Eyes can't
+/// physically rotate much further and trackers sometimes send wild values.
+const MAX_EYE_ROTATION_DEGREES: f32 = 30.0;
+
+/// Clamp each axis of an eye `rotation`, in degrees, to [MAX_EYE_ROTATION_DEGREES].
+pub fn clamp_eye_rotation(rotation: Vector3) -> Vector3 {
+    Vector3::new(
+        rotation
+            .x
+            .clamp(-MAX_EYE_ROTATION_DEGREES, MAX_EYE_ROTATION_DEGREES),
+        rotation
+            .y
+            .clamp(-MAX_EYE_ROTATION_DEGREES, MAX_EYE_ROTATION_DEGREES),
+        rotation
+            .z
+            .clamp(-MAX_EYE_ROTATION_DEGREES, MAX_EYE_ROTATION_DEGREES),
+    )
+}
+
+/// Rotate an eye bone from its rest pose by `rotation`, in degrees. See
+/// [clamp_eye_rotation]. Negative `bone_id`s are ignored.
+///
+/// # Returns
+/// The clamped rotation, in degrees.
+pub fn rotate_eye(skeleton: &mut Gd<Skeleton3D>, bone_id: i32, rotation: Vector3) -> Vector3 {
+    let rotation = clamp_eye_rotation(rotation);
+    if bone_id < 0 {
+        return rotation;
+    }
+
+    let rest = skeleton.get_bone_rest(bone_id).basis.to_quat();
+    skeleton.set_bone_pose_rotation(
+        bone_id,
+        rest * Quaternion::from_euler(rotation * (std::f32::consts::PI / 180.0)),
+    );
+
+    rotation
+}
+
+/// The height of the model at the head bone, in skeleton space. Used as an approximation
+/// for the height of the whole model.
+pub fn skeleton_height(skeleton: &Gd<Skeleton3D>, head_bone_id: i32) -> f32 {
+    skeleton.get_bone_global_pose(head_bone_id).origin.y
+}
+
+/// The default factor for converting VTubeStudio data into puppet units.
+pub const DEFAULT_VTUBE_STUDIO_SCALE: f32 = 0.02;
+
+/// Weight of each new sample when averaging tracking quality inputs.
+const QUALITY_SAMPLE_WEIGHT: f32 = 0.1;
+/// Average head rotation change per frame, in radians, at which the steadiness of
+/// the tracking quality is halved.
+const QUALITY_JITTER_TOLERANCE: f32 = 0.05;
+/// Tracking quality drops to `0.0` when no frames are received for this long.
+const QUALITY_STALE_AFTER: Duration = Duration::from_secs(1);
+
+/// A cheap, normalized measure of how good incoming tracking data is.
+///
+/// The quality is the product of:
+/// - whether a face was found in the last frame, `0.0` or `1.0`
+/// - frame rate stability, `1.0` minus the average deviation of frame intervals
+///   relative to the average frame interval
+/// - steadiness, which drops as the average head rotation change per frame grows
+///
+/// All inputs are exponential moving averages, so each frame is `O(1)`. Note that
+/// steadiness cannot tell jitter apart from fast, intentional head movement.
+#[derive(Debug, Default)]
+pub struct TrackingQuality {
+    last_frame: Option<Instant>,
+    last_rotation: Option<Vector3>,
+    face_found: bool,
+
+    mean_interval: f32,
+    interval_deviation: f32,
+    jitter: f32,
+}
+
+impl TrackingQuality {
+    /// Record a frame received at `now`. `head_rotation` is in radians and should be
+    /// `None` if no face was found.
+    pub fn record(&mut self, now: Instant, head_rotation: Option<Vector3>) {
+        if let Some(last) = self.last_frame {
+            let interval = now.saturating_duration_since(last).as_secs_f32();
+            if self.mean_interval == 0.0 {
+                self.mean_interval = interval;
+            } else {
+                self.interval_deviation = weigh_sample(
+                    self.interval_deviation,
+                    (interval - self.mean_interval).abs(),
+                );
+                self.mean_interval = weigh_sample(self.mean_interval, interval);
+            }
+        }
+        self.last_frame = Some(now);
+
+        self.face_found = head_rotation.is_some();
+        if let Some(rotation) = head_rotation {
+            if let Some(previous) = self.last_rotation {
+                self.jitter = weigh_sample(self.jitter, (rotation - previous).length());
+            }
+            self.last_rotation = Some(rotation);
         }
     }
+
+    /// The tracking quality as of `now`, from `0.0` to `1.0`.
+    pub fn quality(&self, now: Instant) -> f32 {
+        let last_frame = match self.last_frame {
+            Some(v) => v,
+            None => return 0.0,
+        };
+        if !self.face_found || now.saturating_duration_since(last_frame) > QUALITY_STALE_AFTER {
+            return 0.0;
+        }
+
+        let stability = if self.mean_interval > 0.0 {
+            (1.0 - (self.interval_deviation / self.mean_interval)).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let steadiness = QUALITY_JITTER_TOLERANCE / (QUALITY_JITTER_TOLERANCE + self.jitter);
+
+        stability * steadiness
+    }
+}
+
+fn weigh_sample(average: f32, sample: f32) -> f32 {
+    average + (sample - average) * QUALITY_SAMPLE_WEIGHT
+}
+
+/// Split a head `rotation` between the head and additional movement bones, e.g. the
+/// neck and spine. Each additional bone gets its share from `weights`, and the head
+/// gets what is left. Weights are scaled down if they add up to more than `1.0`.
+///
+/// # Returns
+/// The rotation for the head and the rotation for each weight, in order.
+pub fn distribute_rotation(rotation: Vector3, weights: &[f32]) -> (Vector3, Vec<Vector3>) {
+    let total = weights.iter().map(|v| v.max(0.0)).sum::<f32>();
+    let scale = if total > 1.0 { 1.0 / total } else { 1.0 };
+
+    let shares = weights
+        .iter()
+        .map(|v| rotation * (v.max(0.0) * scale))
+        .collect::<Vec<Vector3>>();
+
+    (rotation * (1.0 - total.min(1.0)), shares)
+}
+
+/// Split a head `rotation`, in radians, with [distribute_rotation] and compose each
+/// share with the initial rotation of its bone. `head` and each of the `bones` are
+/// a bone's initial rotation, and for `bones` also its weight.
+///
+/// # Returns
+/// The pose rotation for the head and for each of the `bones`, in order.
+pub fn distribute_bone_rotations(
+    rotation: Vector3,
+    head: Quaternion,
+    bones: &[(Quaternion, f32)],
+) -> (Quaternion, Vec<Quaternion>) {
+    let weights = bones.iter().map(|(_, v)| *v).collect::<Vec<f32>>();
+    let (head_rotation, shares) = distribute_rotation(rotation, &weights);
+
+    (
+        head * Quaternion::from_euler(head_rotation),
+        bones
+            .iter()
+            .zip(shares)
+            .map(|((initial, _), share)| *initial * Quaternion::from_euler(share))
+            .collect(),
+    )
+}
+
+/// Compose a static placement offset with the `initial` transform of a puppet.
+pub fn offset_transform(
+    initial: Transform3D,
+    position: Vector3,
+    rotation_degrees: Vector3,
+) -> Transform3D {
+    let rotation = rotation_degrees * (std::f32::consts::PI / 180.0);
+
+    initial * Transform3D::new(Basis::from_euler(EulerOrder::YXZ, rotation), position)
+}
+
+/// Raw head values from a tracker. Rotation units depend on the tracker.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct HeadPose {
+    pub rotation: Vector3,
+    pub position: Vector3,
+}
+
+/// Neutral head offsets captured per [Tracker]. Trackers send absolute values that
+/// do not line up with the rest pose of the model, so the neutral pose is subtracted
+/// from every frame.
+#[derive(Debug, Default)]
+pub struct Calibration {
+    /// The latest raw pose received from each tracker.
+    latest: HashMap<Tracker, HeadPose>,
+    /// The captured neutral pose for each tracker.
+    offsets: HashMap<Tracker, HeadPose>,
+}
+
+impl Calibration {
+    /// Record the raw `rotation` and `position` from `tracker` and subtract its
+    /// neutral pose, if any. Missing values are neither recorded nor offset.
+    pub fn apply(
+        &mut self,
+        tracker: Tracker,
+        rotation: Option<Vector3>,
+        position: Option<Vector3>,
+    ) -> (Option<Vector3>, Option<Vector3>) {
+        let offset = self.offsets.get(&tracker).copied().unwrap_or_default();
+
+        let latest = self.latest.entry(tracker).or_default();
+        if let Some(v) = rotation {
+            latest.rotation = v;
+        }
+        if let Some(v) = position {
+            latest.position = v;
+        }
+
+        (
+            rotation.map(|v| v - offset.rotation),
+            position.map(|v| v - offset.position),
+        )
+    }
+
+    /// Use the latest raw pose from every tracker as its neutral pose.
+    ///
+    /// # Returns
+    /// Whether any tracker has sent data to calibrate with.
+    pub fn capture(&mut self) -> bool {
+        self.offsets = self.latest.clone();
+
+        !self.offsets.is_empty()
+    }
+
+    /// Forget all neutral poses.
+    pub fn clear(&mut self) {
+        self.offsets.clear();
+    }
+}
+
+/// Axes of incoming head data that should be ignored. Nonzero components are locked.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AxisLocks {
+    pub rotation: Vector3i,
+    pub position: Vector3i,
+}
+
+impl AxisLocks {
+    pub fn rotation(&self, value: Vector3) -> Vector3 {
+        lock_axes(value, self.rotation)
+    }
+
+    pub fn position(&self, value: Vector3) -> Vector3 {
+        lock_axes(value, self.position)
+    }
+}
+
+/// Zero out every component of `value` that is nonzero in `locks`.
+fn lock_axes(value: Vector3, locks: Vector3i) -> Vector3 {
+    Vector3::new(
+        if locks.x != 0 { 0.0 } else { value.x },
+        if locks.y != 0 { 0.0 } else { value.y },
+        if locks.z != 0 { 0.0 } else { value.z },
+    )
+}
+
+/// Load a model of the given `model_type` from `model_path`. Used for reloading a
+/// model in place.
+pub fn load_model(model_path: &GodotString, model_type: &str) -> Option<Gd<Node>> {
+    let bytes = FileAccess::get_file_as_bytes(model_path.clone());
+    if bytes.is_empty() {
+        error!("Unable to read model at {model_path}");
+        return None;
+    }
+
+    crate::loader::load_from_bytes(bytes, model_type)
+}
+
+/// Replace the managed node of a puppet, i.e. its first child, with `node`. The
+/// previous managed node is freed.
+pub fn replace_managed_node(puppet: &mut Gd<Node3D>, node: Gd<Node>) {
+    if let Some(mut previous) = puppet.get_child(0) {
+        puppet.remove_child(previous.clone());
+        previous.queue_free();
+    }
+
+    puppet.add_child(node.clone());
+    puppet.move_child(node, 0);
+}
+
+/// Something that contains blend shapes.
+///
+/// Scenes use [MeshInstance3D]s, but anything can be used to populate
+/// [BlendShapeMapping]s. This allows for mappings to be built from synthetic
+/// data when no Godot scene is available, e.g. in tests.
+pub trait BlendShapeSource {
+    /// The id that is later used to find the mesh again.
+    fn mesh_id(&self) -> i64;
+
+    /// Every blend shape name and its current value.
+    fn blend_shapes(&self) -> Vec<(String, f32)>;
+}
+
+impl BlendShapeSource for Gd<MeshInstance3D> {
+    fn mesh_id(&self) -> i64 {
+        self.instance_id().to_i64()
+    }
+
+    fn blend_shapes(&self) -> Vec<(String, f32)> {
+        let name = self.get_name();
+
+        let mesh = match self.get_mesh() {
+            Some(v) => v,
+            None => {
+                error!("Unable to get mesh from MeshInstance3D {name}, skipping");
+                return vec![];
+            }
+        };
+        let mesh = match mesh.try_cast::<ArrayMesh>() {
+            Some(v) => v,
+            None => {
+                error!("Unable to convert mesh from {name} into ArrayMesh, skipping");
+                return vec![];
+            }
+        };
+
+        (0..mesh.get_blend_shape_count())
+            .map(|i| {
+                (
+                    mesh.get_blend_shape_name(i).to_string(),
+                    self.get_blend_shape_value(i),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Find every direct child of a [Skeleton3D] that is a [MeshInstance3D].
+pub fn find_mesh_instances(skeleton: &Gd<Skeleton3D>) -> Vec<Gd<MeshInstance3D>> {
+    let mesh_instance_3d_name = StringName::from(MESH_INST_3D);
+
+    let mut r = vec![];
+    for child in skeleton.get_children().iter_shared() {
+        // Used for debugging only
+        let child_name = child.get_name();
+
+        if !child.is_class(mesh_instance_3d_name.clone().into()) {
+            debug!("Child {child_name} was not a MeshInstance3D, skipping");
+            continue;
+        }
+
+        match child.try_cast::<MeshInstance3D>() {
+            Some(v) => r.push(v),
+            None => error!(
+                "Skeleton child {child_name} was a MeshInstance3D but was unable to cast to MeshInstance3D"
+            ),
+        }
+    }
+
+    r
+}
+
+// TODO does Godot guarantee unique names for autogenerated blend shape names?
+/// Register every blend shape present on every `source`.
+pub fn populate_blend_shape_mappings<S: BlendShapeSource>(
+    mappings: &mut HashMap<String, BlendShapeMapping>,
+    sources: &[S],
+) {
+    for source in sources {
+        let mesh_id = source.mesh_id();
+
+        for (blend_shape_name, value) in source.blend_shapes() {
+            let blend_shape_property_path = format!("blend_shapes/{}", blend_shape_name);
+
+            mappings.insert(
+                blend_shape_name,
+                BlendShapeMapping::new(mesh_id, blend_shape_property_path, value),
+            );
+        }
+    }
+}
+
+/// Yaw-only rotation that turns a model facing `forward` towards [MODEL_FRONT].
+/// A zero `forward` vector results in no rotation.
+pub fn facing_correction(forward: Vector3) -> Quaternion {
+    let forward = Vector3::new(forward.x, 0.0, forward.z);
+    if forward.length_squared() < f32::EPSILON {
+        return Quaternion::default();
+    }
+
+    Quaternion::from_angle_axis(Vector3::UP, -forward.x.atan2(forward.z))
 }
 
 pub trait Puppet2d: Puppet {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockMesh {
+        id: i64,
+        blend_shapes: Vec<(String, f32)>,
+    }
+
+    impl BlendShapeSource for MockMesh {
+        fn mesh_id(&self) -> i64 {
+            self.id
+        }
+
+        fn blend_shapes(&self) -> Vec<(String, f32)> {
+            self.blend_shapes.clone()
+        }
+    }
+
+    #[test]
+    fn populate_from_mock_meshes() {
+        let meshes = [
+            MockMesh {
+                id: 1,
+                blend_shapes: vec![("Fcl_EYE_Close".to_string(), 0.0)],
+            },
+            MockMesh {
+                id: 2,
+                blend_shapes: vec![
+                    ("Fcl_MTH_A".to_string(), 0.5),
+                    ("Fcl_MTH_I".to_string(), 1.0),
+                ],
+            },
+        ];
+
+        let mut mappings = HashMap::new();
+        populate_blend_shape_mappings(&mut mappings, &meshes);
+
+        assert_eq!(mappings.len(), 3);
+
+        let mapping = mappings.get("Fcl_MTH_A").unwrap();
+        assert_eq!(mapping.mesh_id, 2);
+        assert_eq!(mapping.blend_shape_path, "blend_shapes/Fcl_MTH_A");
+        assert_eq!(mapping.value, 0.5);
+    }
+
+    #[test]
+    fn calibration_offsets_per_tracker() {
+        let mut calibration = Calibration::default();
+
+        assert_eq!(
+            calibration.apply(Tracker::VTubeStudio, Some(Vector3::ONE), Some(Vector3::UP)),
+            (Some(Vector3::ONE), Some(Vector3::UP))
+        );
+        assert!(calibration.capture());
+
+        assert_eq!(
+            calibration.apply(Tracker::VTubeStudio, Some(Vector3::ONE * 2.0), None),
+            (Some(Vector3::ONE), None)
+        );
+        assert_eq!(
+            calibration.apply(Tracker::OpenSeeFace, Some(Vector3::ONE), None),
+            (Some(Vector3::ONE), None)
+        );
+
+        calibration.clear();
+        assert_eq!(
+            calibration.apply(Tracker::VTubeStudio, Some(Vector3::ONE), None),
+            (Some(Vector3::ONE), None)
+        );
+    }
+
+    #[test]
+    fn eye_rotation_is_clamped() {
+        assert_eq!(
+            clamp_eye_rotation(Vector3::new(10.0, -90.0, 45.0)),
+            Vector3::new(10.0, -MAX_EYE_ROTATION_DEGREES, MAX_EYE_ROTATION_DEGREES)
+        );
+    }
+
+    #[test]
+    fn facing_correction_turns_model_to_front() {
+        for forward in [Vector3::FORWARD, Vector3::LEFT, Vector3::RIGHT, MODEL_FRONT] {
+            let corrected = Basis::from_quat(facing_correction(forward)) * forward;
+
+            assert!(corrected.is_equal_approx(MODEL_FRONT), "{forward:?}");
+        }
+    }
+
+    #[test]
+    fn facing_correction_ignores_zero() {
+        assert_eq!(facing_correction(Vector3::ZERO), Quaternion::default());
+    }
+
+    #[test]
+    fn smoothing_head_rotation() {
+        let mut smoothing = Smoothing::default();
+        smoothing.set_factor(SmoothingKind::HeadRotation, 0.5);
+
+        assert_eq!(smoothing.head_rotation(Vector3::ZERO), Vector3::ZERO);
+        assert_eq!(smoothing.head_rotation(Vector3::ONE), Vector3::ONE * 0.5);
+        assert_eq!(smoothing.head_position(Vector3::ZERO), Vector3::ZERO);
+        assert_eq!(smoothing.head_position(Vector3::ONE), Vector3::ONE);
+    }
+
+    #[test]
+    fn smoothing_head_position() {
+        let mut smoothing = Smoothing::default();
+        smoothing.set_factor(SmoothingKind::HeadPosition, 0.75);
+
+        smoothing.head_position(Vector3::ZERO);
+        assert_eq!(smoothing.head_position(Vector3::ONE), Vector3::ONE * 0.25);
+        smoothing.head_rotation(Vector3::ZERO);
+        assert_eq!(smoothing.head_rotation(Vector3::ONE), Vector3::ONE);
+    }
+
+    #[test]
+    fn smoothing_eye_gaze() {
+        let mut smoothing = Smoothing::default();
+        smoothing.set_factor(SmoothingKind::EyeGaze, 0.5);
+
+        smoothing.left_eye(Vector3::ZERO);
+        smoothing.right_eye(Vector3::ONE);
+        assert_eq!(smoothing.left_eye(Vector3::ONE), Vector3::ONE * 0.5);
+        assert_eq!(smoothing.right_eye(Vector3::ZERO), Vector3::ONE * 0.5);
+    }
+
+    #[test]
+    fn smoothing_blend_shapes() {
+        let mut smoothing = Smoothing::default();
+        smoothing.set_factor(SmoothingKind::BlendShapes, 0.5);
+
+        smoothing.blend_shape("blink", 0.0);
+        smoothing.blend_shape("aa", 1.0);
+        assert_eq!(smoothing.blend_shape("blink", 1.0), 0.5);
+        assert_eq!(smoothing.blend_shape("aa", 0.0), 0.5);
+        smoothing.head_rotation(Vector3::ZERO);
+        assert_eq!(smoothing.head_rotation(Vector3::ONE), Vector3::ONE);
+    }
+
+    #[test]
+    fn smoothing_defaults_smooth_head() {
+        let mut smoothing = Smoothing::from(SmoothingFactors::default());
+
+        smoothing.head_rotation(Vector3::ZERO);
+        assert_eq!(smoothing.head_rotation(Vector3::ONE), Vector3::ONE * 0.5);
+        smoothing.head_position(Vector3::ZERO);
+        assert_eq!(smoothing.head_position(Vector3::ONE), Vector3::ONE * 0.5);
+        smoothing.blend_shape("aa", 0.0);
+        assert_eq!(smoothing.blend_shape("aa", 1.0), 1.0);
+    }
+
+    #[test]
+    fn smoothing_factors_round_trip() {
+        let mut smoothing = Smoothing::from(SmoothingFactors::default());
+        smoothing.set_factor(SmoothingKind::EyeGaze, 0.25);
+        smoothing.bypass = true;
+
+        let factors = smoothing.factors();
+        assert_eq!(factors.head_rotation, 0.5);
+        assert_eq!(factors.head_position, 0.5);
+        assert_eq!(factors.eye_gaze, 0.25);
+        assert_eq!(factors.blend_shapes, 0.0);
+        assert_eq!(factors.lip_sync, 0.0);
+    }
+
+    #[test]
+    fn smoothing_lip_sync() {
+        let mut smoothing = Smoothing::default();
+        smoothing.set_factor(SmoothingKind::LipSync, 0.75);
+
+        smoothing.lip_sync(0.0);
+        assert_eq!(smoothing.lip_sync(1.0), 0.25);
+        assert_eq!(smoothing.lip_sync(1.0), 0.4375);
+        smoothing.blend_shape("aa", 0.0);
+        assert_eq!(smoothing.blend_shape("aa", 1.0), 1.0);
+    }
+
+    #[test]
+    fn distribute_rotation_shares() {
+        let rotation = Vector3::new(1.0, 2.0, 0.0);
+
+        let (head, shares) = distribute_rotation(rotation, &[]);
+        assert_eq!(head, rotation);
+        assert!(shares.is_empty());
+
+        let (head, shares) = distribute_rotation(rotation, &[0.25, 0.25]);
+        assert_eq!(head, rotation * 0.5);
+        assert_eq!(shares, vec![rotation * 0.25, rotation * 0.25]);
+
+        let (head, shares) = distribute_rotation(rotation, &[1.0, 1.0]);
+        assert_eq!(head, Vector3::ZERO);
+        assert_eq!(shares, vec![rotation * 0.5, rotation * 0.5]);
+    }
+
+    #[test]
+    fn distribute_bone_rotations_keep_initial_rotation() {
+        let head = Quaternion::from_angle_axis(Vector3::RIGHT, 0.3);
+        let neck = Quaternion::from_angle_axis(Vector3::UP, 0.2);
+
+        let (head_rotation, rotations) =
+            distribute_bone_rotations(Vector3::ZERO, head, &[(neck, 0.5)]);
+        assert!(head_rotation.is_equal_approx(head));
+        assert!(rotations[0].is_equal_approx(neck));
+
+        let rotation = Vector3::new(0.0, 1.0, 0.0);
+        let (head_rotation, rotations) = distribute_bone_rotations(rotation, head, &[(neck, 0.25)]);
+        assert!(head_rotation.is_equal_approx(head * Quaternion::from_euler(rotation * 0.75)));
+        assert!(rotations[0].is_equal_approx(neck * Quaternion::from_euler(rotation * 0.25)));
+    }
+
+    #[test]
+    fn smoothing_bypass() {
+        let mut smoothing = Smoothing::default();
+        smoothing.set_factor(SmoothingKind::BlendShapes, 0.5);
+        smoothing.bypass = true;
+
+        smoothing.blend_shape("blink", 0.0);
+        assert_eq!(smoothing.blend_shape("blink", 1.0), 1.0);
+    }
+
+    #[test]
+    fn locked_roll_stays_neutral() {
+        let locks = AxisLocks {
+            rotation: Vector3i::new(0, 0, 1),
+            position: Vector3i::ZERO,
+        };
+
+        for input in [
+            Vector3::new(0.3, -0.2, 1.2),
+            Vector3::new(-1.0, 0.5, -3.1),
+            Vector3::ZERO,
+        ] {
+            let rotation = locks.rotation(input);
+            assert_eq!(rotation.z, 0.0);
+            assert_eq!(rotation.x, input.x);
+            assert_eq!(rotation.y, input.y);
+        }
+
+        assert_eq!(locks.position(Vector3::ONE), Vector3::ONE);
+    }
+
+    #[test]
+    fn tracking_quality_steady_frames() {
+        let mut quality = TrackingQuality::default();
+        let start = Instant::now();
+        let mut now = start;
+
+        assert_eq!(quality.quality(now), 0.0);
+
+        for _ in 0..60 {
+            now += Duration::from_millis(16);
+            quality.record(now, Some(Vector3::ZERO));
+        }
+
+        assert!(quality.quality(now) > 0.99);
+    }
+
+    #[test]
+    fn tracking_quality_face_lost() {
+        let mut quality = TrackingQuality::default();
+        let mut now = Instant::now();
+
+        quality.record(now, Some(Vector3::ZERO));
+        now += Duration::from_millis(16);
+        quality.record(now, None);
+
+        assert_eq!(quality.quality(now), 0.0);
+    }
+
+    #[test]
+    fn tracking_quality_irregular_frames() {
+        let mut quality = TrackingQuality::default();
+        let mut now = Instant::now();
+
+        for i in 0..60 {
+            now += Duration::from_millis(if i % 2 == 0 { 8 } else { 40 });
+            quality.record(now, Some(Vector3::ZERO));
+        }
+
+        assert!(quality.quality(now) < 0.5);
+    }
+
+    #[test]
+    fn tracking_quality_jitter() {
+        let mut still = TrackingQuality::default();
+        let mut jittery = TrackingQuality::default();
+        let mut now = Instant::now();
+
+        for i in 0..60 {
+            now += Duration::from_millis(16);
+            still.record(now, Some(Vector3::ZERO));
+            jittery.record(
+                now,
+                Some(Vector3::new(if i % 2 == 0 { 0.0 } else { 0.2 }, 0.0, 0.0)),
+            );
+        }
+
+        assert!(jittery.quality(now) < 0.5);
+        assert!(jittery.quality(now) < still.quality(now));
+    }
+
+    #[test]
+    fn tracking_quality_stale() {
+        let mut quality = TrackingQuality::default();
+        let now = Instant::now();
+
+        quality.record(now, Some(Vector3::ZERO));
+
+        assert_eq!(quality.quality(now + Duration::from_secs(2)), 0.0);
+    }
+
+    #[test]
+    fn zero_offset_keeps_initial_transform() {
+        let initial = Transform3D::new(
+            Basis::from_euler(EulerOrder::YXZ, Vector3::new(0.0, 1.0, 0.0)),
+            Vector3::new(1.0, 2.0, 3.0),
+        );
+
+        assert!(offset_transform(initial, Vector3::ZERO, Vector3::ZERO).is_equal_approx(initial));
+    }
+
+    #[test]
+    fn position_offset_translates() {
+        let r = offset_transform(Transform3D::IDENTITY, Vector3::UP, Vector3::ZERO);
+
+        assert!(r.origin.is_equal_approx(Vector3::UP));
+        assert!(r.basis.is_equal_approx(Basis::IDENTITY));
+    }
+
+    #[test]
+    fn populate_without_blend_shapes() {
+        let meshes = [MockMesh {
+            id: 1,
+            blend_shapes: vec![],
+        }];
+
+        let mut mappings = HashMap::new();
+        populate_blend_shape_mappings(&mut mappings, &meshes);
+
+        assert!(mappings.is_empty());
+    }
+}
@@ -1,20 +1,32 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Instant};
 
 use godot::{
-    engine::{ArrayMesh, MeshInstance3D, Skeleton3D},
+    engine::{global::Error, Skeleton3D},
     prelude::*,
 };
 
 use crate::{
-    gstring,
-    model::tracking_data::{IFacialMocapData, VTubeStudioData},
+    cli::Tracker,
+    model::{
+        puppet::SmoothingFactors,
+        tracking_data::{IFacialMocapData, MediaPipeData, VTubeStudioData},
+        RunnerData,
+    },
     Logger,
 };
 
-use super::{BlendShapeMapping, Puppet, Puppet3d};
+use super::{
+    distribute_bone_rotations, find_mesh_instances, load_model, offset_transform,
+    populate_blend_shape_mappings, replace_managed_node, rotate_eye, skeleton_height, AppliedPose,
+    AxisLocks, BlendShapeMapping, Calibration, Puppet, Puppet3d, Smoothing, SmoothingKind,
+    TrackingQuality,
+};
 
-// TODO this is used in both vrm and glb puppet
-const MESH_INST_3D: &str = "MeshInstance3D";
+/// VTubeStudio and iFacialMocap send rotations in degrees.
+const DEG_TO_RAD: f32 = std::f32::consts::PI / 180.0;
+/// Common names for eye bones, checked in order.
+const LEFT_EYE_BONES: [&str; 3] = ["LeftEye", "eye.L", "Eye_L"];
+const RIGHT_EYE_BONES: [&str; 3] = ["RightEye", "eye.R", "Eye_R"];
 
 #[derive(Debug, GodotClass)]
 #[class(base = Node3D)]
@@ -31,126 +43,377 @@ pub struct GlbPuppet {
     pub head_bone: GodotString,
     #[var]
     pub head_bone_id: i32,
+    /// The left eye bone, or `-1` if the model has no known eye bones.
+    #[var]
+    pub left_eye_bone_id: i32,
+    /// The right eye bone, or `-1` if the model has no known eye bones.
+    #[var]
+    pub right_eye_bone_id: i32,
     #[var]
     pub additional_movement_bones: Array<i32>,
+    /// The share of head rotation applied to each of the `additional_movement_bones`.
+    /// Bones without a weight do not move.
+    additional_movement_weights: Vec<f32>,
     #[var]
     pub initial_bone_poses: Dictionary,
+    /// The direction the model faces after import. Zero if no correction is needed.
+    model_facing: Vector3,
+    /// The path to the model, used for reloading the model in place.
+    #[var]
+    pub model_path: GodotString,
+    /// Why `ready` bailed out early. Empty if the puppet loaded successfully.
+    #[var]
+    pub load_error: GodotString,
+    /// Whether `ready` finished setting up everything needed for tracking.
+    tracking_ready: bool,
+    /// Whether a missing skeleton was already logged while handling tracking data.
+    missing_skeleton_logged: bool,
+    /// The pose as of the last applied tracking frame.
+    applied_pose: AppliedPose,
+    /// Smoothing applied to incoming tracking data.
+    smoothing: Smoothing,
+    /// Axes of incoming head data that are ignored.
+    axis_locks: AxisLocks,
+    /// Converts incoming VTubeStudio rotations into radians.
+    rotation_scale: f32,
+    /// Neutral head offsets captured with [GlbPuppet::calibrate].
+    calibration: Calibration,
+    /// How good incoming tracking data is.
+    tracking_quality: TrackingQuality,
+    /// The transform of the puppet before any offset is applied.
+    initial_transform: Transform3D,
+    /// The screen rect the model is pinned to, if any.
+    overlay_rect: Option<Rect2>,
+    /// A static position offset for the whole puppet, composed with tracking.
+    offset_position: Vector3,
+    /// A static rotation offset for the whole puppet in degrees, composed with tracking.
+    offset_rotation_degrees: Vector3,
 
     blend_shape_mappings: HashMap<String, BlendShapeMapping>,
+    /// Lowercase ARKit blend shape names to their key in `blend_shape_mappings`.
+    arkit_blend_shapes: HashMap<String, String>,
 }
 
 #[godot_api]
 impl Node3DVirtual for GlbPuppet {
     fn init(base: godot::obj::Base<Self::Base>) -> Self {
         Self {
-            logger: Logger::create(gstring!("GlbPuppet")),
+            logger: Logger::create("GlbPuppet".into()),
 
             base,
 
             skeleton: None,
             head_bone: GodotString::new(),
             head_bone_id: -1,
+            left_eye_bone_id: -1,
+            right_eye_bone_id: -1,
             additional_movement_bones: Array::new(),
+            additional_movement_weights: vec![],
             initial_bone_poses: Dictionary::new(),
+            model_facing: Vector3::ZERO,
+            model_path: GodotString::new(),
+            load_error: GodotString::new(),
+            tracking_ready: false,
+            missing_skeleton_logged: false,
+            applied_pose: AppliedPose::default(),
+            smoothing: SmoothingFactors::default().into(),
+            axis_locks: AxisLocks::default(),
+            rotation_scale: DEG_TO_RAD,
+            calibration: Calibration::default(),
+            initial_transform: Transform3D::IDENTITY,
+            tracking_quality: TrackingQuality::default(),
+            overlay_rect: None,
+            offset_position: Vector3::ZERO,
+            offset_rotation_degrees: Vector3::ZERO,
 
             blend_shape_mappings: HashMap::new(),
+            arkit_blend_shapes: HashMap::new(),
         }
     }
 
     fn ready(&mut self) {
-        let logger = self.logger();
+        self.load();
+    }
+}
 
-        logger.debug("Starting ready!");
+#[godot_api]
+impl GlbPuppet {
+    /// Emitted once `ready` has finished and the puppet can receive tracking data.
+    #[signal]
+    fn puppet_ready();
+
+    /// Emitted when `ready` bails out early. The puppet will not receive tracking data.
+    #[signal]
+    fn puppet_load_failed(reason: GodotString);
+
+    /// Get the pose as of the last applied tracking frame. This is the pose after
+    /// any processing and thus may differ from the received tracking data.
+    #[func]
+    fn get_current_pose(&self) -> Dictionary {
+        self.applied_pose.to_dict(&self.blend_shape_mappings)
+    }
 
-        match self.find_skeleton(&self.base) {
-            Some(v) => {
-                let _ = self.skeleton.replace(v);
-            }
+    /// Set the direction the model faces after import and correct it so that
+    /// tracking maps to the expected direction.
+    #[func]
+    fn set_model_facing(&mut self, forward: Vector3) {
+        self.model_facing = forward;
+        self.apply_model_facing(forward);
+    }
+
+    #[func]
+    fn get_model_facing(&self) -> Vector3 {
+        self.model_facing
+    }
+
+    /// Apply the saved puppet configuration from `data`. Can be called before `ready`,
+    /// in which case the configuration is applied once the model is loaded.
+    ///
+    /// Applies the model facing, smoothing, and offsets.
+    #[func]
+    fn configure(&mut self, data: Gd<RunnerData>) {
+        let config = data.bind().puppet_3d().clone();
+
+        self.set_model_facing(config.model_facing);
+        self.smoothing.set_factors(config.smoothing);
+        self.set_model_offset(config.offset_position, config.offset_rotation_degrees);
+    }
+
+    /// Write the current puppet configuration, including smoothing, into `data`. The counterpart of
+    /// [Self::configure]. `data` must still be saved afterwards.
+    #[func]
+    fn save_configuration(&self, mut data: Gd<RunnerData>) {
+        let mut data = data.bind_mut();
+
+        let mut config = data.puppet_3d().clone();
+        config.model_facing = self.model_facing;
+        config.smoothing = self.smoothing.factors();
+        config.offset_position = self.offset_position;
+        config.offset_rotation_degrees = self.offset_rotation_degrees;
+        data.set_puppet_3d(config);
+    }
+
+    /// Restore every bone to the pose it had when the puppet was ready, e.g. to
+    /// recenter the puppet after tracking drifts. Smoothing starts over from the
+    /// next received frame.
+    #[func]
+    fn reset_pose(&mut self) -> Error {
+        if self.initial_bone_poses.is_empty() {
+            self.logger()
+                .error("No initial bone poses stored, unable to reset pose");
+            return Error::ERR_UNCONFIGURED;
+        }
+        let skeleton = match self.skeleton.as_mut() {
+            Some(v) => v,
             None => {
-                logger.error("Unable to cast to Skeleton3D, bailing out early!");
-                return;
+                self.log_missing_skeleton();
+                return Error::ERR_UNCONFIGURED;
             }
+        };
+
+        for (bone_id, tx) in self.initial_bone_poses.iter_shared() {
+            let (bone_id, tx) = match (bone_id.try_to::<i32>(), tx.try_to::<Transform3D>()) {
+                (Ok(bone_id), Ok(tx)) => (bone_id, tx),
+                _ => continue,
+            };
+
+            skeleton.set_bone_pose_position(bone_id, tx.origin);
+            skeleton.set_bone_pose_rotation(bone_id, tx.basis.to_quat());
+            skeleton.set_bone_pose_scale(bone_id, tx.basis.scale());
         }
 
-        let skeleton = self.skeleton.as_ref().unwrap();
+        self.smoothing.reset();
+        self.applied_pose = AppliedPose::default();
 
-        self.head_bone_id = skeleton.find_bone(self.head_bone.clone());
-        if self.head_bone_id < 0 {
-            logger.error("No head bone found!");
-            return;
+        Error::OK
+    }
+
+    /// Set the share of head rotation applied to each of the `additional_movement_bones`,
+    /// in the same order. The head gets whatever share is left.
+    #[func]
+    fn set_additional_movement_weights(&mut self, weights: PackedFloat32Array) -> Error {
+        if weights.as_slice().iter().any(|v| *v < 0.0) {
+            self.logger()
+                .error("Additional movement weights must not be negative");
+            return Error::ERR_INVALID_PARAMETER;
         }
 
-        // TODO init skeleton bone transforms from config
+        self.additional_movement_weights = weights.to_vec();
 
-        // This must be done after loading the user's custom rest pose
-        for i in 0..skeleton.get_bone_count() {
-            self.initial_bone_poses.insert(i, skeleton.get_bone_pose(i));
+        Error::OK
+    }
+
+    /// How good incoming tracking data is, from `0.0` to `1.0`. See [TrackingQuality]
+    /// for how this is calculated.
+    #[func]
+    fn tracking_quality(&self) -> f32 {
+        self.tracking_quality.quality(Instant::now())
+    }
+
+    /// Apply a static placement offset to the puppet. The offset is composed with
+    /// tracking, which is applied to the puppet's bones.
+    #[func]
+    fn set_model_offset(&mut self, position: Vector3, rotation_degrees: Vector3) {
+        self.offset_position = position;
+        self.offset_rotation_degrees = rotation_degrees;
+
+        // Otherwise the offset is applied once ready
+        if self.tracking_ready {
+            self.base.set_transform(offset_transform(
+                self.initial_transform,
+                position,
+                rotation_degrees,
+            ));
         }
+    }
 
-        // Pre-allocate the name here and then clone it in the loop
-        let mesh_instance_3d_name = StringName::from(MESH_INST_3D);
+    #[func]
+    fn get_model_offset_position(&self) -> Vector3 {
+        self.offset_position
+    }
 
-        // Populating the blend shape mappings is extremely verbose
-        for child in skeleton.get_children().iter_shared() {
-            // Used for debugging only
-            let child_name = child.get_name();
+    #[func]
+    fn get_model_offset_rotation_degrees(&self) -> Vector3 {
+        self.offset_rotation_degrees
+    }
 
-            if !child.is_class(mesh_instance_3d_name.clone().into()) {
-                logger.debug(format!(
-                    "Child {child_name} was not a MeshInstance3D, skipping"
-                ));
-                continue;
-            }
+    /// Reload the model from `model_path` in place, e.g. after the model was
+    /// re-exported. User settings are kept. `puppet_ready` is emitted again on success.
+    #[func]
+    fn reload_model(&mut self) -> Error {
+        if self.model_path.is_empty() {
+            self.logger()
+                .error("No model path set, unable to reload model");
+            return Error::ERR_UNCONFIGURED;
+        }
 
-            let child = child.try_cast::<MeshInstance3D>();
-            if child.is_none() {
-                logger.error(
-                    format!("Skeleton child {child_name} was a MeshInstance3D but was unable to cast to MeshInstance3D")
-                );
-                continue;
+        let node = match load_model(&self.model_path, "glb") {
+            Some(v) => v,
+            None => {
+                self.logger()
+                    .error(format!("Unable to reload model at {}", self.model_path));
+                return Error::ERR_FILE_CANT_OPEN;
             }
+        };
+
+        self.tracking_ready = false;
+
+        self.skeleton = None;
+        self.blend_shape_mappings.clear();
+        self.arkit_blend_shapes.clear();
+        self.initial_bone_poses.clear();
+        self.smoothing.reset();
+        self.applied_pose = AppliedPose::default();
+        self.tracking_quality = TrackingQuality::default();
+        self.missing_skeleton_logged = false;
+        self.load_error = GodotString::new();
+
+        // ready captures the initial transform again, so the offset must not be applied twice
+        let initial_transform = self.initial_transform;
+        self.base.set_transform(initial_transform);
+        replace_managed_node(&mut self.base, node);
+
+        self.load();
+
+        if self.tracking_ready {
+            Error::OK
+        } else {
+            Error::ERR_CANT_CREATE
+        }
+    }
 
-            let child = child.unwrap();
-            let mesh = match child.get_mesh() {
-                Some(v) => v,
-                None => {
-                    logger.error(format!(
-                        "Unable to get mesh from MeshInstance3D {child_name}, skipping"
-                    ));
-                    continue;
-                }
-            };
-            let mesh = match mesh.try_cast::<ArrayMesh>() {
-                Some(v) => v,
-                None => {
-                    logger.error(format!(
-                        "Unable to convert mesh from {child_name} into ArrayMesh, skipping"
-                    ));
-                    continue;
-                }
-            };
+    #[func(rename = capture_frame)]
+    fn capture_frame_bound(&self) -> PackedByteArray {
+        self.capture_frame()
+    }
+
+    /// Pin the model to `screen_rect`, so that it stays in place regardless of
+    /// tracking. Useful for compositing the model as an overlay.
+    #[func]
+    fn set_overlay_transform(&mut self, screen_rect: Rect2) {
+        self.overlay_rect = Some(screen_rect);
 
-            for i in 0..mesh.get_blend_shape_count() {
-                let blend_shape_name = mesh.get_blend_shape_name(i).to_string();
-                let blend_shape_property_path = format!("blend_shapes/{}", blend_shape_name);
-                let value = child.get_blend_shape_value(i);
-
-                self.blend_shape_mappings.insert(
-                    blend_shape_name.clone(),
-                    BlendShapeMapping::new(
-                        // TODO this seems strange
-                        child.instance_id().to_i64(),
-                        blend_shape_property_path,
-                        value,
-                    ),
-                );
+        match self.skeleton.as_ref() {
+            Some(v) => {
+                self.apply_overlay_transform(screen_rect, skeleton_height(v, self.head_bone_id))
             }
+            None => self
+                .logger()
+                .debug("No skeleton found, the model will be pinned once loaded"),
         }
     }
-}
 
-#[godot_api]
-impl GlbPuppet {
+    /// Set the smoothing `factor` for the given kind of tracking data. `0.0` disables
+    /// smoothing while values closer to `1.0` smooth more heavily.
+    ///
+    /// See [SmoothingKind] for the available kinds.
+    #[func]
+    fn set_smoothing(&mut self, kind: i64, factor: f32) -> Error {
+        match SmoothingKind::try_from(kind) {
+            Ok(v) => {
+                self.smoothing.set_factor(v, factor);
+                Error::OK
+            }
+            Err(e) => {
+                self.logger().error(format!("Unknown smoothing kind {e}"));
+                Error::ERR_INVALID_PARAMETER
+            }
+        }
+    }
+
+    /// Ignore axes of incoming head data. Nonzero components of `rotation_locks` and
+    /// `position_locks` zero out that axis after smoothing is applied.
+    #[func]
+    fn set_axis_locks(&mut self, rotation_locks: Vector3i, position_locks: Vector3i) {
+        self.axis_locks = AxisLocks {
+            rotation: rotation_locks,
+            position: position_locks,
+        };
+    }
+
+    /// Set the factor for converting incoming VTubeStudio rotations into radians.
+    /// Defaults to converting degrees into radians, larger values exaggerate head movement.
+    #[func]
+    fn set_rotation_scale(&mut self, rotation_scale: f32) {
+        self.rotation_scale = rotation_scale;
+    }
+
+    #[func]
+    fn get_rotation_scale(&self) -> f32 {
+        self.rotation_scale
+    }
+
+    /// Use the latest head pose from each tracker as the neutral pose, so that the
+    /// puppet is centered while the user sits straight. Offsets are stored per tracker.
+    #[func]
+    fn calibrate(&mut self) -> Error {
+        if self.calibration.capture() {
+            Error::OK
+        } else {
+            self.logger()
+                .error("No tracking data received yet, unable to calibrate");
+            Error::ERR_UNAVAILABLE
+        }
+    }
+
+    /// Forget neutral poses captured with [GlbPuppet::calibrate].
+    #[func]
+    fn clear_calibration(&mut self) {
+        self.calibration.clear();
+    }
+
+    /// Apply raw tracking values directly, skipping smoothing. Useful for hitting a
+    /// precise pose while calibrating. Previous settings apply again once disabled.
+    #[func]
+    fn set_calibration_mode(&mut self, enabled: bool) {
+        self.smoothing.bypass = enabled;
+    }
+
+    #[func]
+    fn get_calibration_mode(&self) -> bool {
+        self.smoothing.bypass
+    }
+
     #[func(rename = handle_vtube_studio)]
     fn handle_vtube_studio_bound(&mut self, data: Gd<VTubeStudioData>) {
         self.handle_vtube_studio(data);
@@ -162,8 +425,13 @@ impl GlbPuppet {
     }
 
     #[func(rename = handle_media_pipe)]
-    fn handle_media_pipe_bound(&mut self, projection: Projection, blend_shapes: Dictionary) {
-        self.handle_media_pipe(projection, blend_shapes);
+    fn handle_media_pipe_bound(&mut self, data: Gd<MediaPipeData>) {
+        self.handle_media_pipe(data);
+    }
+
+    #[func(rename = handle_open_see_face)]
+    fn handle_open_see_face_bound(&mut self, data: Dictionary) {
+        self.handle_open_see_face(data);
     }
 
     #[func(rename = handle_i_facial_mocap)]
@@ -172,37 +440,277 @@ impl GlbPuppet {
     }
 }
 
+impl GlbPuppet {
+    /// Set up everything needed for tracking from the managed node. Shared by
+    /// `ready` and [GlbPuppet::reload_model].
+    fn load(&mut self) {
+        let logger = self.logger();
+
+        logger.debug("Loading model");
+
+        match self.find_skeleton(&self.base) {
+            Some(v) => {
+                let _ = self.skeleton.replace(v);
+            }
+            None => {
+                self.fail_load("Unable to cast to Skeleton3D, bailing out early!");
+                return;
+            }
+        }
+
+        let skeleton = self.skeleton.clone().unwrap();
+
+        self.head_bone_id = skeleton.find_bone(self.head_bone.clone());
+        if self.head_bone_id < 0 {
+            self.fail_load("No head bone found!");
+            return;
+        }
+
+        self.left_eye_bone_id = find_first_bone(&skeleton, &LEFT_EYE_BONES);
+        self.right_eye_bone_id = find_first_bone(&skeleton, &RIGHT_EYE_BONES);
+        if self.left_eye_bone_id < 0 || self.right_eye_bone_id < 0 {
+            logger.debug("No eye bones found, eye tracking is disabled");
+        }
+
+        // TODO init skeleton bone transforms from config
+
+        // This must be done after loading the user's custom rest pose
+        for i in 0..skeleton.get_bone_count() {
+            self.initial_bone_poses.insert(i, skeleton.get_bone_pose(i));
+        }
+
+        populate_blend_shape_mappings(
+            &mut self.blend_shape_mappings,
+            &find_mesh_instances(&skeleton),
+        );
+        for (name, mapping) in self.blend_shape_mappings.iter_mut() {
+            mapping.cache();
+            self.arkit_blend_shapes
+                .insert(name.to_lowercase(), name.clone());
+        }
+
+        self.check_model_facing(&skeleton);
+        self.apply_model_facing(self.model_facing);
+        self.initial_transform = self.base.get_transform();
+        self.base.set_transform(offset_transform(
+            self.initial_transform,
+            self.offset_position,
+            self.offset_rotation_degrees,
+        ));
+        if let Some(rect) = self.overlay_rect {
+            self.apply_overlay_transform(rect, skeleton_height(&skeleton, self.head_bone_id));
+        }
+
+        self.tracking_ready = true;
+        // Listeners usually call back into the puppet, which is still borrowed here
+        self.base.call_deferred(
+            "emit_signal".into(),
+            &[StringName::from("puppet_ready").to_variant()],
+        );
+    }
+
+    /// Record why `ready` bailed out early and notify listeners.
+    fn fail_load(&mut self, reason: impl Into<GodotString>) {
+        let reason = reason.into();
+
+        self.logger().error(reason.to_string());
+        self.load_error = reason.clone();
+        // Deferred for the same reason as `puppet_ready`
+        self.base.call_deferred(
+            "emit_signal".into(),
+            &[
+                StringName::from("puppet_load_failed").to_variant(),
+                reason.to_variant(),
+            ],
+        );
+    }
+
+    /// Log that tracking data was received without a skeleton. Only logs once
+    /// to avoid flooding the logs, since tracking data is received every frame.
+    fn log_missing_skeleton(&mut self) {
+        if !self.missing_skeleton_logged {
+            self.logger()
+                .error("Received tracking data without a skeleton, ignoring tracking data");
+            self.missing_skeleton_logged = true;
+        }
+    }
+}
+
+/// The rotation of `bone_id` in `initial_bone_poses`, or identity if there is none.
+/// Tracking rotations are composed with it, so that the rest pose is kept.
+fn initial_rotation(initial_bone_poses: &Dictionary, bone_id: i32) -> Quaternion {
+    initial_bone_poses
+        .get(bone_id)
+        .and_then(|v| v.try_to::<Transform3D>().ok())
+        .map(|v| v.basis.to_quat())
+        .unwrap_or_default()
+}
+
+/// Find the first of `names` in `skeleton`.
+///
+/// # Returns
+/// The bone id, or `-1` if no bone was found.
+fn find_first_bone(skeleton: &Gd<Skeleton3D>, names: &[&str]) -> i32 {
+    names
+        .iter()
+        .map(|v| skeleton.find_bone((*v).into()))
+        .find(|v| *v >= 0)
+        .unwrap_or(-1)
+}
+
 impl Puppet for GlbPuppet {
     fn logger(&self) -> Logger {
         self.logger.bind().clone()
     }
 
-    fn managed_node(&self) -> Gd<Node> {
-        match self.base.get_child(0) {
-            Some(v) => v,
-            None => {
-                self.logger()
-                    .error("Unable to get managed node, this is a major error!");
-
-                panic!("Bailing out!");
-            }
-        }
+    fn try_managed_node(&self) -> Option<Gd<Node>> {
+        self.base.get_child(0)
     }
 }
 
 impl Puppet3d for GlbPuppet {
     fn handle_i_facial_mocap(&mut self, data: Gd<IFacialMocapData>) {
-        //
-    }
+        if !self.tracking_ready {
+            return;
+        }
 
-    fn handle_vtube_studio(&mut self, data: Gd<VTubeStudioData>) {
         let data = data.bind();
-        let skeleton = self.skeleton.as_mut().unwrap();
+        let skeleton = match self.skeleton.as_mut() {
+            Some(v) => v,
+            None => {
+                self.log_missing_skeleton();
+                return;
+            }
+        };
+
+        // iFacialMocap sends rotations in degrees
+        self.tracking_quality
+            .record(Instant::now(), Some(data.rotation * DEG_TO_RAD));
+
+        let (rotation, position) = self.calibration.apply(
+            Tracker::IFacialMocap,
+            Some(data.rotation),
+            Some(data.position),
+        );
+        if let Some(rotation) = rotation {
+            let rotation = self
+                .axis_locks
+                .rotation(self.smoothing.head_rotation(rotation * DEG_TO_RAD));
 
-        if let Some(rotation) = data.rotation {
             skeleton.set_bone_pose_rotation(
                 self.head_bone_id,
-                Quaternion::from_euler(Vector3::new(rotation.y, rotation.x, rotation.z) * 0.02),
+                initial_rotation(&self.initial_bone_poses, self.head_bone_id)
+                    * Quaternion::from_euler(rotation),
+            );
+            self.applied_pose.head_rotation = rotation;
+        }
+        if let Some(position) = position {
+            let position = self
+                .axis_locks
+                .position(self.smoothing.head_position(position));
+            let initial_position = self
+                .initial_bone_poses
+                .get(self.head_bone_id)
+                .and_then(|v| v.try_to::<Transform3D>().ok())
+                .map(|v| v.origin)
+                .unwrap_or_default();
+
+            skeleton.set_bone_pose_position(self.head_bone_id, initial_position + position);
+            self.applied_pose.head_position = position;
+        }
+
+        self.applied_pose.left_eye = rotate_eye(
+            skeleton,
+            self.left_eye_bone_id,
+            self.smoothing.left_eye(data.left_eye),
+        );
+        self.applied_pose.right_eye = rotate_eye(
+            skeleton,
+            self.right_eye_bone_id,
+            self.smoothing.right_eye(data.right_eye),
+        );
+
+        for (name, value) in data.blend_shapes.iter() {
+            let name = name.to_lowercase();
+            let mapping = match self
+                .arkit_blend_shapes
+                .get(&name)
+                .and_then(|v| self.blend_shape_mappings.get_mut(v))
+            {
+                Some(v) => v,
+                None => continue,
+            };
+
+            mapping.set_value(self.smoothing.blend_shape(&name, *value));
+        }
+    }
+
+    fn handle_vtube_studio(&mut self, data: Gd<VTubeStudioData>) {
+        if !self.tracking_ready {
+            return;
+        }
+
+        let data = data.bind();
+        let skeleton = match self.skeleton.as_mut() {
+            Some(v) => v,
+            None => {
+                self.log_missing_skeleton();
+                return;
+            }
+        };
+
+        self.tracking_quality.record(
+            Instant::now(),
+            data.rotation
+                .filter(|_| data.face_found.unwrap_or(true))
+                .map(|v| Vector3::new(v.y, v.x, v.z) * self.rotation_scale),
+        );
+
+        let (rotation, _) = self
+            .calibration
+            .apply(Tracker::VTubeStudio, data.rotation, None);
+        if let Some(rotation) = rotation {
+            let rotation = self.axis_locks.rotation(self.smoothing.head_rotation(
+                Vector3::new(rotation.y, rotation.x, rotation.z) * self.rotation_scale,
+            ));
+
+            let bone_ids = self
+                .additional_movement_bones
+                .iter_shared()
+                .zip(self.additional_movement_weights.iter().copied())
+                .collect::<Vec<(i32, f32)>>();
+            let bones = bone_ids
+                .iter()
+                .map(|(bone_id, weight)| {
+                    (
+                        initial_rotation(&self.initial_bone_poses, *bone_id),
+                        *weight,
+                    )
+                })
+                .collect::<Vec<(Quaternion, f32)>>();
+            let (head_rotation, rotations) = distribute_bone_rotations(
+                rotation,
+                initial_rotation(&self.initial_bone_poses, self.head_bone_id),
+                &bones,
+            );
+
+            for ((bone_id, _), bone_rotation) in bone_ids.iter().zip(rotations) {
+                skeleton.set_bone_pose_rotation(*bone_id, bone_rotation);
+            }
+
+            skeleton.set_bone_pose_rotation(self.head_bone_id, head_rotation);
+            self.applied_pose.head_rotation = rotation;
+        }
+
+        if let Some(v) = data.eye_left {
+            self.applied_pose.left_eye =
+                rotate_eye(skeleton, self.left_eye_bone_id, self.smoothing.left_eye(v));
+        }
+        if let Some(v) = data.eye_right {
+            self.applied_pose.right_eye = rotate_eye(
+                skeleton,
+                self.right_eye_bone_id,
+                self.smoothing.right_eye(v),
             );
         }
     }
@@ -211,11 +719,79 @@ impl Puppet3d for GlbPuppet {
         self.handle_vtube_studio(data);
     }
 
-    fn handle_media_pipe(&mut self, projection: Projection, _blend_shapes: Dictionary) {
-        let skeleton = self.skeleton.as_mut().unwrap();
+    fn handle_open_see_face(&mut self, data: Dictionary) {
+        if !self.tracking_ready {
+            return;
+        }
+
+        let skeleton = match self.skeleton.as_mut() {
+            Some(v) => v,
+            None => {
+                self.log_missing_skeleton();
+                return;
+            }
+        };
+
+        let raw_rotation = match data
+            .get("quaternion")
+            .and_then(|v| v.try_to::<Quaternion>().ok())
+        {
+            Some(v) => Basis::from_quat(v).to_euler(EulerOrder::YXZ),
+            None => return,
+        };
+        let face_found = data
+            .get("success")
+            .and_then(|v| v.try_to::<bool>().ok())
+            .unwrap_or(true);
+
+        self.tracking_quality
+            .record(Instant::now(), face_found.then_some(raw_rotation));
+        let (raw_rotation, _) =
+            self.calibration
+                .apply(Tracker::OpenSeeFace, Some(raw_rotation), None);
+        let rotation = self.axis_locks.rotation(
+            self.smoothing
+                .head_rotation(raw_rotation.unwrap_or_default()),
+        );
+
+        skeleton.set_bone_pose_rotation(
+            self.head_bone_id,
+            initial_rotation(&self.initial_bone_poses, self.head_bone_id)
+                * Quaternion::from_euler(rotation),
+        );
+        self.applied_pose.head_rotation = rotation;
+    }
 
-        let tx = Transform3D::from_projection(projection);
+    fn handle_media_pipe(&mut self, data: Gd<MediaPipeData>) {
+        if !self.tracking_ready {
+            return;
+        }
 
-        skeleton.set_bone_pose_rotation(self.head_bone_id, tx.basis.to_quat());
+        let skeleton = match self.skeleton.as_mut() {
+            Some(v) => v,
+            None => {
+                self.log_missing_skeleton();
+                return;
+            }
+        };
+
+        let tx = Transform3D::from_projection(data.bind().projection);
+        let raw_rotation = tx.basis.to_euler(EulerOrder::YXZ);
+        self.tracking_quality
+            .record(Instant::now(), Some(raw_rotation));
+        let (raw_rotation, _) =
+            self.calibration
+                .apply(Tracker::MediaPipe, Some(raw_rotation), None);
+        let rotation = self.axis_locks.rotation(
+            self.smoothing
+                .head_rotation(raw_rotation.unwrap_or_default()),
+        );
+
+        skeleton.set_bone_pose_rotation(
+            self.head_bone_id,
+            initial_rotation(&self.initial_bone_poses, self.head_bone_id)
+                * Quaternion::from_euler(rotation),
+        );
+        self.applied_pose.head_rotation = rotation;
     }
 }
@@ -0,0 +1,279 @@
+use godot::{
+    engine::{global::Error, Sprite2D, Texture2D},
+    prelude::*,
+};
+
+use crate::Logger;
+
+use super::{Puppet, Puppet2d};
+
+/// How long a blink lasts, in seconds.
+const BLINK_DURATION: f32 = 0.15;
+/// How long the mouth stays in [PngState::TalkClosed] after the amplitude drops
+/// below the threshold, in seconds. Keeps the puppet from flickering back to idle
+/// between syllables.
+const TALK_HOLD: f32 = 0.3;
+
+/// The sprite states a [PngPuppet] can be in.
+#[repr(i64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PngState {
+    Idle = 0,
+    Blink = 1,
+    TalkOpen = 2,
+    TalkClosed = 3,
+}
+
+impl TryFrom<i64> for PngState {
+    type Error = String;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Idle),
+            1 => Ok(Self::Blink),
+            2 => Ok(Self::TalkOpen),
+            3 => Ok(Self::TalkClosed),
+            _ => Err(format!("Invalid png state {value}")),
+        }
+    }
+}
+
+/// Decides which [PngState] to show from audio amplitude and a blink timer.
+#[derive(Debug)]
+pub struct PngStateMachine {
+    /// Amplitudes at or above this open the mouth.
+    pub amplitude_threshold: f32,
+    /// Seconds between blinks. `0.0` disables blinking.
+    pub blink_interval: f32,
+
+    since_blink: f32,
+    since_talk: Option<f32>,
+}
+
+impl Default for PngStateMachine {
+    fn default() -> Self {
+        Self {
+            amplitude_threshold: 0.1,
+            blink_interval: 4.0,
+
+            since_blink: 0.0,
+            since_talk: None,
+        }
+    }
+}
+
+impl PngStateMachine {
+    /// Advance the timers by `delta` seconds and pick the state for `amplitude`.
+    /// Talking takes priority over blinking.
+    pub fn update(&mut self, delta: f32, amplitude: f32) -> PngState {
+        self.since_talk = if amplitude >= self.amplitude_threshold {
+            Some(0.0)
+        } else {
+            self.since_talk
+                .map(|v| v + delta)
+                .filter(|v| *v < TALK_HOLD)
+        };
+
+        let blinking = if self.blink_interval > 0.0 {
+            self.since_blink += delta;
+            if self.since_blink >= self.blink_interval + BLINK_DURATION {
+                self.since_blink %= self.blink_interval + BLINK_DURATION;
+            }
+            self.since_blink >= self.blink_interval
+        } else {
+            false
+        };
+
+        match self.since_talk {
+            Some(v) if v == 0.0 => PngState::TalkOpen,
+            Some(_) => PngState::TalkClosed,
+            None if blinking => PngState::Blink,
+            None => PngState::Idle,
+        }
+    }
+}
+
+/// A PNGTuber puppet. Swaps the texture of its [Sprite2D] based on lip sync
+/// amplitude and a blink timer.
+#[derive(Debug, GodotClass)]
+#[class(base = Node2D)]
+pub struct PngPuppet {
+    #[var]
+    pub logger: Gd<Logger>,
+
+    #[base]
+    base: Base<Node2D>,
+
+    state_machine: PngStateMachine,
+    /// The textures for each [PngState], indexed by the state.
+    textures: [Option<Gd<Texture2D>>; 4],
+    /// The state currently shown.
+    state: Option<PngState>,
+    /// The last received lip sync amplitude.
+    amplitude: f32,
+    /// The lip sync receiver amplitudes are polled from every frame, if any.
+    lip_sync: Option<Gd<RefCounted>>,
+}
+
+#[godot_api]
+impl Node2DVirtual for PngPuppet {
+    fn init(base: godot::obj::Base<Self::Base>) -> Self {
+        Self {
+            logger: Logger::create("PngPuppet".into()),
+
+            base,
+
+            state_machine: PngStateMachine::default(),
+            textures: [None, None, None, None],
+            state: None,
+            amplitude: 0.0,
+            lip_sync: None,
+        }
+    }
+
+    fn ready(&mut self) {
+        // Use the loaded texture for idle if no idle texture was set
+        if self.textures[PngState::Idle as usize].is_none() {
+            self.textures[PngState::Idle as usize] = self.sprite().and_then(|v| v.get_texture());
+        }
+    }
+
+    fn process(&mut self, delta: f64) {
+        if let Some(amplitude) = self.lip_sync.as_mut().and_then(|v| {
+            v.call("poll".into(), &[])
+                .try_to::<Dictionary>()
+                .ok()?
+                .get("amplitude")?
+                .try_to::<f32>()
+                .ok()
+        }) {
+            self.amplitude = amplitude;
+        }
+
+        let state = self.state_machine.update(delta as f32, self.amplitude);
+        if self.state == Some(state) {
+            return;
+        }
+        self.state = Some(state);
+
+        // Missing textures fall back to idle so partially configured puppets still work
+        let texture = match self.textures[state as usize]
+            .clone()
+            .or_else(|| self.textures[PngState::Idle as usize].clone())
+        {
+            Some(v) => v,
+            None => return,
+        };
+        if let Some(mut sprite) = self.sprite() {
+            sprite.set_texture(texture);
+        }
+    }
+}
+
+#[godot_api]
+impl PngPuppet {
+    /// Set the texture shown for a [PngState].
+    #[func]
+    fn set_state_texture(&mut self, state: i64, texture: Gd<Texture2D>) -> Error {
+        let state = match PngState::try_from(state) {
+            Ok(v) => v,
+            Err(e) => {
+                self.logger().error(e);
+                return Error::ERR_INVALID_PARAMETER;
+            }
+        };
+
+        self.textures[state as usize] = Some(texture);
+        // Force the texture to be reapplied on the next frame
+        self.state = None;
+
+        Error::OK
+    }
+
+    /// Handle the latest amplitude from lip sync, generally from 0.0-1.0.
+    #[func]
+    fn handle_lip_sync(&mut self, amplitude: f32) {
+        self.amplitude = amplitude;
+    }
+
+    /// Poll amplitudes from a running `lip_sync` receiver every frame, instead of
+    /// waiting for [Self::handle_lip_sync] to be called. Pass `null` to disconnect.
+    ///
+    /// The receiver is called dynamically, since the `LipSync` receiver is optional.
+    /// Any object with a `poll` method returning an `amplitude`, like `LipSync`, works.
+    #[func]
+    fn set_lip_sync(&mut self, lip_sync: Option<Gd<RefCounted>>) {
+        self.lip_sync = lip_sync;
+    }
+
+    /// Set the amplitude at which the mouth opens.
+    #[func]
+    fn set_amplitude_threshold(&mut self, amplitude_threshold: f32) {
+        self.state_machine.amplitude_threshold = amplitude_threshold.max(0.0);
+    }
+
+    /// Set the seconds between blinks. Set to `0.0` to disable blinking.
+    #[func]
+    fn set_blink_interval(&mut self, blink_interval: f32) {
+        self.state_machine.blink_interval = blink_interval.max(0.0);
+    }
+
+    /// The [PngState] currently shown, or `-1` if nothing was shown yet.
+    #[func]
+    fn get_state(&self) -> i64 {
+        self.state.map(|v| v as i64).unwrap_or(-1)
+    }
+}
+
+impl PngPuppet {
+    fn sprite(&self) -> Option<Gd<Sprite2D>> {
+        self.base
+            .get_child(0)
+            .and_then(|v| v.try_cast::<Sprite2D>())
+    }
+}
+
+impl Puppet for PngPuppet {
+    fn logger(&self) -> Logger {
+        self.logger.bind().clone()
+    }
+
+    fn try_managed_node(&self) -> Option<Gd<Node>> {
+        self.base.get_child(0)
+    }
+}
+
+impl Puppet2d for PngPuppet {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn talking_opens_and_holds_mouth() {
+        let mut sm = PngStateMachine {
+            blink_interval: 0.0,
+            ..Default::default()
+        };
+
+        assert_eq!(sm.update(0.1, 0.0), PngState::Idle);
+        assert_eq!(sm.update(0.1, 0.5), PngState::TalkOpen);
+        assert_eq!(sm.update(0.1, 0.0), PngState::TalkClosed);
+        assert_eq!(sm.update(0.1, 0.0), PngState::TalkClosed);
+        assert_eq!(sm.update(0.2, 0.0), PngState::Idle);
+    }
+
+    #[test]
+    fn blinks_on_interval() {
+        let mut sm = PngStateMachine {
+            blink_interval: 1.0,
+            ..Default::default()
+        };
+
+        assert_eq!(sm.update(0.9, 0.0), PngState::Idle);
+        assert_eq!(sm.update(0.2, 0.0), PngState::Blink);
+        assert_eq!(sm.update(0.1, 0.0), PngState::Idle);
+        // Talking takes priority over blinking
+        assert_eq!(sm.update(0.9, 0.5), PngState::TalkOpen);
+    }
+}
@@ -1,28 +1,110 @@
-use std::{
-    collections::{hash_map::RandomState, HashMap},
-    sync::{Arc, RwLock},
-};
+use std::{collections::HashMap, time::Instant};
 
 use godot::{
-    engine::{
-        animation::TrackType, global::Error, AnimationPlayer, ArrayMesh, MeshInstance3D, Skeleton3D,
-    },
+    engine::{animation::TrackType, global::Error, AnimationPlayer, BoneMap, Skeleton3D},
     prelude::*,
 };
-use log::{debug, error, info};
-use rayon::prelude::*;
+use log::{debug, error};
 
 use crate::{
-    gstring,
-    model::{self, tracking_data::VTubeStudioData, IFacialMocapData},
+    cli::Tracker,
+    model::{self, tracking_data::VTubeStudioData, IFacialMocapData, MediaPipeData, RunnerData},
     Logger,
 };
 
-use super::{BlendShapeMapping, IkTargets3d, Puppet, Puppet3d};
+use super::{
+    find_mesh_instances, load_model, offset_transform, populate_blend_shape_mappings,
+    replace_managed_node, rotate_eye, skeleton_height, AppliedPose, AxisLocks, BlendShapeMapping,
+    Calibration, IkTargets3d, Puppet, Puppet3d, Smoothing, SmoothingKind, TrackingQuality,
+    DEFAULT_VTUBE_STUDIO_SCALE,
+};
 
 const ANIM_PLAYER: &str = "AnimationPlayer";
-const MESH_INST_3D: &str = "MeshInstance3D";
+const DEG_TO_RAD: f32 = std::f32::consts::PI / 180.0;
+/// Decaying blend shapes snap to their target once they are this close.
+const DECAY_EPSILON: f32 = 0.001;
+
+// MediaPipe pose landmark indices
+const MP_LEFT_WRIST: usize = 15;
+const MP_RIGHT_WRIST: usize = 16;
+const MP_LEFT_HIP: usize = 23;
+const MP_RIGHT_HIP: usize = 24;
+const MP_POSE_LANDMARK_COUNT: usize = 33;
+// MediaPipe hand landmark indices
+const MP_HAND_WRIST: usize = 0;
+const MP_HAND_INDEX_MCP: usize = 5;
+const MP_HAND_MIDDLE_MCP: usize = 9;
+const MP_HAND_PINKY_MCP: usize = 17;
+const MP_HAND_LANDMARK_COUNT: usize = 21;
 const VRM_META: &str = "vrm_meta";
+const FIRST_PERSON_MESH_ANNOTATIONS: &str = "first_person_mesh_annotations";
+const HUMANOID_BONE_MAPPING: &str = "humanoid_bone_mapping";
+
+const L_SHOULDER: &str = "LeftShoulder";
+const R_SHOULDER: &str = "RightShoulder";
+const L_UPPER_ARM: &str = "LeftUpperArm";
+const R_UPPER_ARM: &str = "RightUpperArm";
+const L_LOWER_ARM: &str = "LeftLowerArm";
+const R_LOWER_ARM: &str = "RightLowerArm";
+const L_EYE: &str = "LeftEye";
+const R_EYE: &str = "RightEye";
+
+/// The 52 ARKit blend shapes that make up Perfect Sync, lowercased to match
+/// expression mapping names.
+const PERFECT_SYNC_EXPRESSIONS: [&str; 52] = [
+    "browdownleft",
+    "browdownright",
+    "browinnerup",
+    "browouterupleft",
+    "browouterupright",
+    "cheekpuff",
+    "cheeksquintleft",
+    "cheeksquintright",
+    "eyeblinkleft",
+    "eyeblinkright",
+    "eyelookdownleft",
+    "eyelookdownright",
+    "eyelookinleft",
+    "eyelookinright",
+    "eyelookoutleft",
+    "eyelookoutright",
+    "eyelookupleft",
+    "eyelookupright",
+    "eyesquintleft",
+    "eyesquintright",
+    "eyewideleft",
+    "eyewideright",
+    "jawforward",
+    "jawleft",
+    "jawopen",
+    "jawright",
+    "mouthclose",
+    "mouthdimpleleft",
+    "mouthdimpleright",
+    "mouthfrownleft",
+    "mouthfrownright",
+    "mouthfunnel",
+    "mouthleft",
+    "mouthlowerdownleft",
+    "mouthlowerdownright",
+    "mouthpressleft",
+    "mouthpressright",
+    "mouthpucker",
+    "mouthright",
+    "mouthrolllower",
+    "mouthrollupper",
+    "mouthshruglower",
+    "mouthshrugupper",
+    "mouthsmileleft",
+    "mouthsmileright",
+    "mouthstretchleft",
+    "mouthstretchright",
+    "mouthupperupleft",
+    "mouthupperupright",
+    "nosesneerleft",
+    "nosesneerright",
+    "tongueout",
+];
 
 #[repr(i64)]
 #[derive(Debug, Clone, Copy, Property, Export)]
@@ -56,7 +138,7 @@ impl Into<model::puppet::VrmType> for VrmType {
 /// additional blend shapes that are not present in the base VRM specification.
 #[derive(Debug)]
 enum VrmFeatures {
-    /// Base VRM 0.0 and 1.0 specification.
+    /// Base VRM 0.0 and 1.0 specification. Eye ids are `-1` if the bone is missing.
     Base { left_eye_id: i32, right_eye_id: i32 },
     /// Generally refers to an additional 52 blend shapes provided outside
     /// of the VRM specification.
@@ -66,8 +148,8 @@ enum VrmFeatures {
 impl Default for VrmFeatures {
     fn default() -> Self {
         Self::Base {
-            left_eye_id: i32::default(),
-            right_eye_id: i32::default(),
+            left_eye_id: -1,
+            right_eye_id: -1,
         }
     }
 }
@@ -116,6 +198,62 @@ pub struct VrmPuppet {
 
     blend_shape_mappings: HashMap<String, BlendShapeMapping>,
     expression_mappings: HashMap<String, Vec<String>>,
+    /// Lowercased Perfect Sync names to the blend shapes of the same name. Only used
+    /// for [VrmFeatures::PerfectSync] models, where ARKit-named tracking data is
+    /// applied directly instead of going through the VRM expressions.
+    perfect_sync_mappings: HashMap<String, Vec<String>>,
+
+    /// Time constant, in seconds, used when fading out expressions whose incoming
+    /// value is lower than the currently applied value. `0.0` disables decay.
+    expression_decay: f32,
+    /// Blend shape mapping keys to the value they are fading out towards. Decay is
+    /// advanced in `process`, so expressions keep fading out when tracking stops.
+    decaying_blend_shapes: HashMap<String, f32>,
+    /// Whether raw tracking values should be applied directly. While enabled, all
+    /// processing is skipped but the configured values are kept.
+    calibration_mode: bool,
+    /// Neutral head offsets captured with [VrmPuppet::calibrate].
+    calibration: Calibration,
+    /// The only expression that should be applied from tracking data. Used for debugging.
+    solo_expression: Option<String>,
+    /// Smoothing applied to incoming tracking data.
+    smoothing: Smoothing,
+    /// The currently running expression test, if any.
+    expression_test: Option<ExpressionTest>,
+    /// The transform of the puppet before any offset is applied.
+    initial_transform: Transform3D,
+    /// Axes of incoming head data that are ignored.
+    axis_locks: AxisLocks,
+    /// Converts incoming VTubeStudio positions into puppet units.
+    position_scale: f32,
+    /// How good incoming tracking data is.
+    tracking_quality: TrackingQuality,
+
+    /// The path to the model, used for reloading the model in place.
+    #[var]
+    pub model_path: GodotString,
+    /// Why `ready` bailed out early. Empty if the puppet loaded successfully.
+    #[var]
+    pub load_error: GodotString,
+    /// Whether `ready` finished setting up everything needed for tracking.
+    tracking_ready: bool,
+    /// Whether a missing skeleton was already logged while handling tracking data.
+    missing_skeleton_logged: bool,
+    /// The mouth expression driven by lip sync, resolved once the model is loaded.
+    lip_sync_expression: Option<&'static str>,
+    /// Whether a missing lip sync expression was already logged while handling audio.
+    missing_lip_sync_logged: bool,
+    /// The pose as of the last applied tracking frame.
+    applied_pose: AppliedPose,
+
+    /// Whether the lower arms should have their roll corrected when a-posing.
+    #[var]
+    pub correct_lower_arm_roll: bool,
+
+    /// Meshes that should only be visible in first-person.
+    first_person_only_meshes: Vec<Gd<Node3D>>,
+    /// Meshes that should only be visible in third-person, e.g. the head.
+    third_person_only_meshes: Vec<Gd<Node3D>>,
 }
 
 #[godot_api]
@@ -137,172 +275,115 @@ impl Node3DVirtual for VrmPuppet {
 
             blend_shape_mappings: HashMap::new(),
             expression_mappings: HashMap::new(),
+            perfect_sync_mappings: HashMap::new(),
+
+            expression_decay: 0.0,
+            decaying_blend_shapes: HashMap::new(),
+            calibration_mode: false,
+            calibration: Calibration::default(),
+            solo_expression: None,
+            smoothing: model::puppet::SmoothingFactors::default().into(),
+            expression_test: None,
+            axis_locks: AxisLocks::default(),
+            position_scale: DEFAULT_VTUBE_STUDIO_SCALE,
+            initial_transform: Transform3D::IDENTITY,
+            tracking_quality: TrackingQuality::default(),
+
+            model_path: GodotString::new(),
+            load_error: GodotString::new(),
+            tracking_ready: false,
+            missing_skeleton_logged: false,
+            lip_sync_expression: None,
+            missing_lip_sync_logged: false,
+            applied_pose: AppliedPose::default(),
+
+            correct_lower_arm_roll: false,
+
+            first_person_only_meshes: vec![],
+            third_person_only_meshes: vec![],
         }
     }
 
     fn ready(&mut self) {
-        let logger = self.logger();
-
-        logger.debug("Starting ready!");
-
-        match self.find_skeleton(&self.base) {
-            Some(v) => {
-                let _ = self.skeleton.replace(v);
-            }
-            None => {
-                logger.error("Unable to find skeleton, bailing out early!");
-                return;
-            }
-        }
-
-        let skeleton = self.skeleton.as_ref().unwrap();
-
-        self.puppet3d.head_bone_id = skeleton.find_bone(self.puppet3d.head_bone.clone().into());
-        if self.puppet3d.head_bone_id < 0 {
-            logger.error("No head bone found!");
-            return;
-        }
-
-        // TODO init skeleton bone transforms from config
-
-        // This must be done after loading the user's custom rest pose
-        for i in 0..skeleton.get_bone_count() {
-            self.puppet3d
-                .initial_bone_poses
-                .insert(i, skeleton.get_bone_pose(i));
-        }
-
-        let mut ik_targets_3d = IkTargets3d::default();
-        // TODO these are all hardcoded, maybe pull values from elsewhere?
-        if let v @ Some(_) = self.create_armature("HeadArmature", "Head") {
-            ik_targets_3d.head = v;
-
-            let mut tx = skeleton.get_bone_global_pose(skeleton.find_bone("Head".into()));
-            tx.origin = self.base.to_global(tx.origin);
-            ik_targets_3d.head_starting_transform = tx;
-        }
-        if let v @ Some(_) = self.create_armature("LeftHandArmature", "LeftHand") {
-            ik_targets_3d.left_hand = v;
-
-            let mut tx = skeleton.get_bone_global_pose(skeleton.find_bone("LeftHand".into()));
-            tx.origin = self.base.to_global(tx.origin);
-            ik_targets_3d.left_hand_starting_transform = tx;
-        }
-        if let v @ Some(_) = self.create_armature("RightHandArmature", "RightHand") {
-            ik_targets_3d.right_hand = v;
+        self.load();
+    }
 
-            let mut tx = skeleton.get_bone_global_pose(skeleton.find_bone("RightHand".into()));
-            tx.origin = self.base.to_global(tx.origin);
-            ik_targets_3d.right_hand_starting_transform = tx;
-        }
-        if let v @ Some(_) = self.create_armature("HipsArmature", "Hips") {
-            ik_targets_3d.hips = v;
-        }
-        if let v @ Some(_) = self.create_armature("LeftFootArmature", "LeftFoot") {
-            ik_targets_3d.left_foot = v;
-        }
-        if let v @ Some(_) = self.create_armature("RightFootArmature", "RightFoot") {
-            ik_targets_3d.right_foot = v;
-        }
-        self.ik_targets_3d = Some(Gd::new(ik_targets_3d));
+    fn process(&mut self, delta: f64) {
+        self.decay_blend_shapes(delta as f32);
 
-        populate_blend_shape_mappings(&mut self.blend_shape_mappings, skeleton);
-        if let Some(v) = self.find_animation_player() {
-            populate_and_modify_expression_mappings(&mut self.expression_mappings, &v);
-        } else {
-            error!("Unable to find Animation Player, blend shapes will not work!");
-        }
+        if let Some(test) = self.expression_test.as_mut() {
+            test.elapsed += delta as f32;
 
-        let vrm_meta = match self
-            .managed_node()
-            .get(VRM_META.into())
-            .try_to::<Gd<Resource>>()
-        {
-            Ok(v) => v,
-            Err(e) => {
-                logger.error(format!("Unable to get vrm metadata, bailing out! {e:?}"));
-                return;
+            let name = test.name.clone();
+            if test.elapsed >= test.duration {
+                self.stop_expression_test();
+            } else {
+                let weight = expression_test_weight(test.elapsed, test.duration);
+                self.set_expression(&name, weight);
             }
-        };
-        self.vrm_meta = Some(vrm_meta);
-
-        // self.vrm_features = match self.vrm_puppet.vrm_type {
-        //     model::puppet::VrmType::Base => VrmFeatures::new_base(self),
-        //     model::puppet::VrmType::PerfectSync => VrmFeatures::new_perfect_sync(self),
-        // };
-
-        // if self.a_pose() != Error::OK {
-        //     logger.error("Unable to a-pose");
-        // }
+        }
     }
 }
 
-// TODO does Godot guarantee unique names for autogenerated blend shape names?
-/// Iterate through every child node of a [Skeleton3D] and, if that node is a
-/// [MeshInstance3D], register every blend shape present on the mesh.
-fn populate_blend_shape_mappings(
-    // mappings: &mut Arc<RwLock<HashMap<String, BlendShapeMapping>>>,
-    mappings: &mut HashMap<String, BlendShapeMapping>,
-    skeleton: &Gd<Skeleton3D>,
-) {
-    let mesh_instance_3d_name = StringName::from(MESH_INST_3D);
-    // let mut mappings = mappings.write().unwrap();
-
-    for child in skeleton.get_children().iter_shared() {
-        // Used for debugging only
-        let child_name = child.get_name();
-
-        if !child.is_class(mesh_instance_3d_name.clone().into()) {
-            debug!("Child {child_name} was not a MeshInstance3D, skipping");
-            continue;
-        }
+/// Resolve a humanoid `profile_bone_name`, e.g. [L_SHOULDER], to the name of the bone
+/// in the skeleton. Falls back to `profile_bone_name` if there is no mapping for it.
+fn humanoid_bone_name(bone_map: Option<&Gd<BoneMap>>, profile_bone_name: &str) -> String {
+    bone_map
+        .map(|v| {
+            v.get_skeleton_bone_name(profile_bone_name.into())
+                .to_string()
+        })
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| profile_bone_name.to_string())
+}
 
-        let child = child.try_cast::<MeshInstance3D>();
-        if child.is_none() {
-            error!(
-                "Skeleton child {child_name} was a MeshInstance3D but was unable to cast to MeshInstance3D");
-            continue;
-        }
+/// The mouth expression driven by lip sync, if the model has one.
+fn lip_sync_expression(mappings: &HashMap<String, Vec<String>>) -> Option<&'static str> {
+    ["aa", "a"].into_iter().find(|v| mappings.contains_key(*v))
+}
 
-        let child = child.unwrap();
-        let mesh = match child.get_mesh() {
-            Some(v) => v,
-            None => {
-                error!("Unable to get mesh from MeshInstance3D {child_name}, skipping");
-                continue;
-            }
-        };
-        let mesh = match mesh.try_cast::<ArrayMesh>() {
-            Some(v) => v,
-            None => {
-                error!("Unable to convert mesh from {child_name} into ArrayMesh, skipping");
-                continue;
-            }
-        };
+/// When a mesh with a VRM first-person annotation should be visible.
+#[derive(Debug, PartialEq, Eq)]
+enum FirstPersonFlag {
+    Both,
+    FirstPersonOnly,
+    ThirdPersonOnly,
+}
 
-        for i in 0..mesh.get_blend_shape_count() {
-            let blend_shape_name = mesh.get_blend_shape_name(i).to_string();
-            let blend_shape_property_path = format!("blend_shapes/{}", blend_shape_name);
-            let value = child.get_blend_shape_value(i);
+/// Parse a VRM first-person annotation `flag`, ignoring case. VRM 0.0 uses
+/// `FirstPersonOnly` while VRM 1.0 uses `firstPersonOnly`.
+fn first_person_flag(flag: &str) -> FirstPersonFlag {
+    // Auto requires generating a headless mesh, so treat it like Both for now
+    match flag.to_lowercase().as_str() {
+        "firstpersononly" => FirstPersonFlag::FirstPersonOnly,
+        "thirdpersononly" => FirstPersonFlag::ThirdPersonOnly,
+        _ => FirstPersonFlag::Both,
+    }
+}
 
-            let instance_id = child.instance_id().to_i64();
-            // Quick sanity check to make sure instance ids are valid
-            if let None = InstanceId::try_from_i64(instance_id)
-                .map(|v| Gd::<MeshInstance3D>::try_from_instance_id(v))
-            {
-                error!(
-                    "Invalid instance id for {}::{blend_shape_name}, skipping!",
-                    child.get_name()
-                );
-                continue;
-            }
+/// Whether every Perfect Sync expression is present in the `mappings`.
+fn is_perfect_sync(mappings: &HashMap<String, Vec<String>>) -> bool {
+    PERFECT_SYNC_EXPRESSIONS
+        .iter()
+        .all(|v| mappings.contains_key(*v))
+}
 
-            mappings.insert(
-                blend_shape_name.clone(),
-                BlendShapeMapping::new(instance_id, blend_shape_property_path, value),
-            );
+/// Map each Perfect Sync name to the blend shapes with the same name, ignoring case.
+/// Perfect Sync names without a matching blend shape are skipped.
+fn perfect_sync_mappings<'a, I>(blend_shape_names: I) -> HashMap<String, Vec<String>>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    let mut r: HashMap<String, Vec<String>> = HashMap::new();
+
+    for name in blend_shape_names {
+        let lowercase_name = name.to_lowercase();
+        if PERFECT_SYNC_EXPRESSIONS.contains(&lowercase_name.as_str()) {
+            r.entry(lowercase_name).or_default().push(name.clone());
         }
     }
+
+    r
 }
 
 /// Extract VRM and Perfect Sync mappings from the godot-vrm [AnimationPlayer].
@@ -369,11 +450,24 @@ fn populate_and_modify_expression_mappings(
 
 #[godot_api]
 impl VrmPuppet {
+    /// Emitted once `ready` has finished and the puppet can receive tracking data.
+    #[signal]
+    fn puppet_ready();
+
+    /// Emitted when `ready` bails out early. The puppet will not receive tracking data.
+    #[signal]
+    fn puppet_load_failed(reason: GodotString);
+
     /// Move VRM bones into an a-pose.
     #[func]
     pub fn a_pose(&mut self) -> Error {
         let logger = self.logger();
 
+        let bone_map = self.humanoid_bone_map();
+        if bone_map.is_none() {
+            logger.debug("No humanoid bone mapping found, using default bone names");
+        }
+
         let skeleton = match &mut self.skeleton {
             Some(v) => v,
             None => {
@@ -382,13 +476,19 @@ impl VrmPuppet {
             }
         };
 
-        const L_SHOULDER: &str = "LeftShoulder";
-        const R_SHOULDER: &str = "RightShoulder";
-        const L_UPPER_ARM: &str = "LeftUpperArm";
-        const R_UPPER_ARM: &str = "RightUpperArm";
+        // Roll is measured against the lower arms' orientations before any adjustment
+        let lower_arms = [L_LOWER_ARM, R_LOWER_ARM].map(|bone_name| {
+            let bone_idx =
+                skeleton.find_bone(humanoid_bone_name(bone_map.as_ref(), bone_name).into());
+            let rest =
+                (bone_idx >= 0).then(|| skeleton.get_bone_global_pose(bone_idx).basis.to_quat());
+
+            (bone_name, bone_idx, rest)
+        });
 
         for bone_name in [L_SHOULDER, R_SHOULDER, L_UPPER_ARM, R_UPPER_ARM] {
-            let bone_idx = skeleton.find_bone(bone_name.into());
+            let bone_idx =
+                skeleton.find_bone(humanoid_bone_name(bone_map.as_ref(), bone_name).into());
             if bone_idx < 0 {
                 logger.error(format!(
                     "Bone not found while trying to a-pose: {bone_name}"
@@ -396,36 +496,337 @@ impl VrmPuppet {
                 continue;
             }
 
-            let quat = match bone_name {
-                L_SHOULDER => {
-                    skeleton.get_bone_pose_rotation(bone_idx)
-                        * Quaternion::from_angle_axis(Vector3::LEFT, 0.34)
-                }
+            let quat = skeleton.get_bone_pose_rotation(bone_idx) * a_pose_adjustment(bone_name);
+
+            skeleton.set_bone_pose_rotation(bone_idx, quat);
+        }
+
+        if self.correct_lower_arm_roll {
+            for (lower_arm, bone_idx, rest) in lower_arms {
+                let rest = match rest {
+                    Some(v) => v,
+                    None => {
+                        logger.error(format!(
+                            "Bone not found while trying to correct arm roll: {lower_arm}"
+                        ));
+                        continue;
+                    }
+                };
+                let posed = skeleton.get_bone_global_pose(bone_idx).basis.to_quat();
+
+                let quat = skeleton.get_bone_pose_rotation(bone_idx)
+                    * lower_arm_roll_correction(rest, posed);
+
+                skeleton.set_bone_pose_rotation(bone_idx, quat);
+            }
+        }
+
+        Error::OK
+    }
 
-                L_UPPER_ARM => {
-                    skeleton.get_bone_pose_rotation(bone_idx)
-                        * Quaternion::from_angle_axis(Vector3::RIGHT, 0.52)
+    /// Get the pose as of the last applied tracking frame. This is the pose after
+    /// any processing and thus may differ from the received tracking data.
+    #[func]
+    fn get_current_pose(&self) -> Dictionary {
+        self.applied_pose.to_dict(&self.blend_shape_mappings)
+    }
+
+    /// Reload the model from `model_path` in place, e.g. after the model was
+    /// re-exported. User settings are kept. `puppet_ready` is emitted again on success.
+    #[func]
+    fn reload_model(&mut self) -> Error {
+        if self.model_path.is_empty() {
+            self.logger()
+                .error("No model path set, unable to reload model");
+            return Error::ERR_UNCONFIGURED;
+        }
+
+        let node = match load_model(&self.model_path, "vrm") {
+            Some(v) => v,
+            None => {
+                self.logger()
+                    .error(format!("Unable to reload model at {}", self.model_path));
+                return Error::ERR_FILE_CANT_OPEN;
+            }
+        };
+
+        self.stop_expression_test();
+        self.tracking_ready = false;
+
+        if let Some(ik) = self.ik_targets_3d.take() {
+            let ik = ik.bind();
+            for target in [
+                &ik.head,
+                &ik.left_hand,
+                &ik.right_hand,
+                &ik.hips,
+                &ik.left_foot,
+                &ik.right_foot,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                target.clone().queue_free();
+            }
+        }
+        self.skeleton = None;
+        self.vrm_meta = None;
+        self.blend_shape_mappings.clear();
+        self.expression_mappings.clear();
+        self.perfect_sync_mappings.clear();
+        self.first_person_only_meshes.clear();
+        self.third_person_only_meshes.clear();
+        self.puppet3d.initial_bone_poses.clear();
+        self.smoothing.reset();
+        self.decaying_blend_shapes.clear();
+        self.applied_pose = AppliedPose::default();
+        self.tracking_quality = TrackingQuality::default();
+        self.missing_skeleton_logged = false;
+        self.lip_sync_expression = None;
+        self.missing_lip_sync_logged = false;
+        self.load_error = GodotString::new();
+
+        // ready captures the initial transform again, so the offset must not be applied twice
+        let initial_transform = self.initial_transform;
+        self.base.set_transform(initial_transform);
+        replace_managed_node(&mut self.base, node);
+
+        self.load();
+
+        if self.tracking_ready {
+            Error::OK
+        } else {
+            Error::ERR_CANT_CREATE
+        }
+    }
+
+    /// Apply the saved puppet configuration from `data`. Must be called after `ready`
+    /// has set up the scene.
+    ///
+    /// Applies the head bone, bone poses, ik target transforms, model facing, smoothing,
+    /// and offsets. Tint is not applied, since VRM materials have no common tint parameter.
+    #[func]
+    fn configure(&mut self, data: Gd<RunnerData>) -> Error {
+        if !self.tracking_ready {
+            self.logger()
+                .error("Puppet is not ready, unable to apply configuration");
+            return Error::ERR_UNCONFIGURED;
+        }
+
+        let config = data.bind().puppet_3d().clone();
+        self.vrm_puppet = data.bind().vrm_puppet().clone();
+        let mut skeleton = match self.skeleton.clone() {
+            Some(v) => v,
+            None => {
+                self.log_missing_skeleton();
+                return Error::ERR_UNCONFIGURED;
+            }
+        };
+
+        if !config.head_bone.is_empty() {
+            let head_bone_id = skeleton.find_bone(config.head_bone.clone().into());
+            if head_bone_id < 0 {
+                self.logger()
+                    .error(format!("No head bone named {}", config.head_bone));
+                return Error::ERR_DOES_NOT_EXIST;
+            }
+
+            self.puppet3d.head_bone = config.head_bone.clone();
+            self.puppet3d.head_bone_id = head_bone_id;
+        }
+
+        for (bone_id, tx) in config.initial_bone_poses.iter() {
+            if *bone_id < 0 || *bone_id >= skeleton.get_bone_count() {
+                self.logger()
+                    .error(format!("Invalid bone id {bone_id} in config, skipping"));
+                continue;
+            }
+
+            skeleton.set_bone_pose_position(*bone_id, tx.origin);
+            skeleton.set_bone_pose_rotation(*bone_id, tx.basis.to_quat());
+            skeleton.set_bone_pose_scale(*bone_id, tx.basis.scale());
+            self.puppet3d.initial_bone_poses.insert(*bone_id, *tx);
+        }
+        self.puppet3d.additional_movement_bones = config.additional_movement_bones.clone();
+
+        if let Some(mut ik) = self.ik_targets_3d.clone() {
+            let mut ik = ik.bind_mut();
+            for (name, tx) in config.ik_target_transforms.iter() {
+                if !ik.set_target_transform(name, *tx) {
+                    self.logger()
+                        .error(format!("Invalid ik target {name} in config, skipping"));
                 }
+            }
+        }
+        self.puppet3d.ik_target_transforms = config.ik_target_transforms.clone();
+
+        self.set_model_facing(config.model_facing);
+
+        self.smoothing.set_factors(config.smoothing);
+        self.puppet3d.smoothing = config.smoothing;
+
+        self.set_model_offset(config.offset_position, config.offset_rotation_degrees);
+        if let Some(rect) = config.overlay_rect {
+            self.set_overlay_transform(rect);
+        }
+
+        Error::OK
+    }
+
+    /// Write the current puppet configuration, including smoothing, into `data`.
+    /// The counterpart of [Self::configure]. `data` must still be saved afterwards.
+    #[func]
+    fn save_configuration(&self, mut data: Gd<RunnerData>) {
+        let mut data = data.bind_mut();
+        data.set_puppet_3d(self.puppet3d.clone());
+        data.set_vrm_puppet(self.vrm_puppet.clone());
+    }
 
-                R_SHOULDER => {
-                    skeleton.get_bone_pose_rotation(bone_idx)
-                        * Quaternion::from_angle_axis(Vector3::LEFT, 0.34)
+    /// Apply many expressions at once, e.g. for scripted emotes. `values` is a mapping
+    /// of expression names to weights. If `clear_others` is set, all expressions not
+    /// in `values` are set to `0.0`.
+    ///
+    /// Weights are applied directly, skipping smoothing, decay, and blink settings,
+    /// so that live tracking is not affected.
+    #[func(rename = apply_expressions)]
+    fn apply_expressions_bound(&mut self, values: Dictionary, clear_others: bool) {
+        let logger = self.logger();
+
+        let mut expressions: HashMap<String, f32> =
+            HashMap::from_iter(values.iter_shared().filter_map(|(k, v)| {
+                match v
+                    .try_to::<f32>()
+                    .or_else(|_| v.try_to::<i64>().map(|v| v as f32))
+                {
+                    Ok(v) => Some((k.to_string().to_lowercase(), v)),
+                    Err(_) => {
+                        logger.error(format!("Invalid weight {v} for expression {k}, skipping"));
+                        None
+                    }
                 }
+            }));
+        if clear_others {
+            for name in self.expression_mappings.keys() {
+                expressions.entry(name.clone()).or_insert(0.0);
+            }
+        }
+
+        for (name, value) in expressions {
+            self.set_expression(&name, value);
+        }
+    }
+
+    /// Open the mouth from an audio RMS `amplitude` from 0.0-1.0. Drives the `aa`
+    /// expression, or `a` for models that use that name. The amplitude is smoothed
+    /// with the lip sync smoothing factor, see [Self::set_smoothing].
+    #[func]
+    fn handle_lip_sync(&mut self, amplitude: f32) {
+        if !self.tracking_ready {
+            return;
+        }
 
-                R_UPPER_ARM => {
-                    skeleton.get_bone_pose_rotation(bone_idx)
-                        * Quaternion::from_angle_axis(Vector3::RIGHT, 0.52)
+        let name = match self.lip_sync_expression {
+            Some(v) => v,
+            None => {
+                // Audio frames arrive many times a second, so only log once
+                if !self.missing_lip_sync_logged {
+                    self.logger()
+                        .error("No aa or a expression found, unable to apply lip sync");
+                    self.missing_lip_sync_logged = true;
                 }
+                return;
+            }
+        };
 
-                _ => unreachable!("This should never happen!"),
-            };
+        let value = self.smoothing.lip_sync(amplitude.clamp(0.0, 1.0));
+        self.set_expression(name, value);
+    }
 
-            skeleton.set_bone_pose_rotation(bone_idx, quat);
+    /// Animate the expression `name` from `0.0` to `1.0` and back over `duration`
+    /// seconds. Used for checking if an expression works without a tracker.
+    ///
+    /// Only one test runs at a time, starting a new test stops the previous one.
+    /// The test is stopped as soon as tracking data is received.
+    #[func]
+    fn test_expression(&mut self, name: GodotString, duration: f32) -> Error {
+        let name = name.to_string().to_lowercase();
+        if !self.expression_mappings.contains_key(&name) {
+            self.logger()
+                .error(format!("No expression named {name}, unable to test"));
+            return Error::ERR_DOES_NOT_EXIST;
+        }
+        if duration <= 0.0 {
+            self.logger()
+                .error(format!("Invalid test duration {duration}"));
+            return Error::ERR_INVALID_PARAMETER;
         }
 
+        self.stop_expression_test();
+        self.expression_test = Some(ExpressionTest {
+            name,
+            duration,
+            elapsed: 0.0,
+        });
+
         Error::OK
     }
 
+    /// Stop the currently running expression test, if any.
+    #[func]
+    fn cancel_test_expression(&mut self) {
+        self.stop_expression_test();
+    }
+
+    /// Dump all expression and blend shape mappings. Meant to be attached to bug
+    /// reports when expressions do not work.
+    ///
+    /// # Returns
+    /// A [Dictionary] containing:
+    /// - `expression_mappings`: expression name to an [Array] of blend shape keys
+    /// - `blend_shape_mappings`: blend shape name to its `mesh_id`, `blend_shape_path`,
+    ///   and current `value`
+    #[func]
+    fn dump_mappings(&self) -> Dictionary {
+        let mut r = Dictionary::new();
+
+        r.insert(
+            "expression_mappings",
+            self.expression_mappings
+                .iter()
+                .map(|(k, v)| {
+                    (
+                        GodotString::from(k),
+                        v.iter()
+                            .map(GodotString::from)
+                            .collect::<Array<GodotString>>(),
+                    )
+                })
+                .collect::<Dictionary>(),
+        );
+        r.insert(
+            "blend_shape_mappings",
+            self.blend_shape_mappings
+                .iter()
+                .map(|(k, v)| (GodotString::from(k), v.to_dict()))
+                .collect::<Dictionary>(),
+        );
+
+        r
+    }
+
+    /// Set the direction the model faces after import and correct it so that
+    /// tracking maps to the expected direction.
+    #[func]
+    fn set_model_facing(&mut self, forward: Vector3) {
+        self.puppet3d.model_facing = forward;
+        self.apply_model_facing(forward);
+    }
+
+    #[func]
+    fn get_model_facing(&self) -> Vector3 {
+        self.puppet3d.model_facing
+    }
+
     #[func]
     fn get_head_bone(&self) -> GodotString {
         self.puppet3d.head_bone.clone().into()
@@ -489,9 +890,11 @@ impl VrmPuppet {
         self.vrm_puppet.blink_threshold
     }
 
+    /// Set the value above which blinks are snapped to fully closed. Can be changed
+    /// while tracking. Set to `0.0` to disable snapping.
     #[func]
     fn set_blink_threshold(&mut self, blink_threshold: f32) {
-        self.vrm_puppet.blink_threshold = blink_threshold;
+        self.vrm_puppet.blink_threshold = blink_threshold.clamp(0.0, 1.0);
     }
 
     #[func]
@@ -514,222 +917,815 @@ impl VrmPuppet {
         self.vrm_puppet.use_raw_eye_rotation = use_raw_eye_rotation;
     }
 
-    // #[func]
-    // fn get_vrm_type(&self) -> VrmType {
-    //     self.vrm_puppet.vrm_type.into()
-    // }
+    /// Set the time constant, in seconds, used for fading out expressions. Rising
+    /// values are always applied immediately. Set to `0.0` to disable decay.
+    ///
+    /// Decay is advanced every frame, so expressions fade out even if the tracker
+    /// stops sending data.
+    #[func]
+    fn set_expression_decay(&mut self, expression_decay: f32) {
+        self.expression_decay = expression_decay.max(0.0);
+    }
 
-    // #[func]
-    // fn set_vrm_type(&mut self, vrm_type: VrmType) {
-    //     self.vrm_puppet.vrm_type = vrm_type.into();
-    // }
+    #[func]
+    fn get_expression_decay(&self) -> f32 {
+        self.expression_decay
+    }
 
-    #[func(rename = handle_i_facial_mocap)]
-    fn handle_i_facial_mocap_bound(&mut self, data: Gd<IFacialMocapData>) {
-        self.handle_i_facial_mocap(data);
+    /// How good incoming tracking data is, from `0.0` to `1.0`. See [TrackingQuality]
+    /// for how this is calculated.
+    #[func]
+    fn tracking_quality(&self) -> f32 {
+        self.tracking_quality.quality(Instant::now())
     }
 
-    #[func(rename = handle_vtube_studio)]
-    fn handle_vtube_studio_bound(&mut self, data: Gd<VTubeStudioData>) {
-        self.handle_vtube_studio(data);
+    /// Apply a static placement offset to the puppet. The offset is composed with
+    /// tracking, which is applied to the puppet's bones.
+    #[func]
+    fn set_model_offset(&mut self, position: Vector3, rotation_degrees: Vector3) {
+        self.puppet3d.offset_position = position;
+        self.puppet3d.offset_rotation_degrees = rotation_degrees;
+
+        // Otherwise the offset is applied once ready
+        if self.tracking_ready {
+            self.base.set_transform(offset_transform(
+                self.initial_transform,
+                position,
+                rotation_degrees,
+            ));
+        }
     }
 
-    #[func(rename = handle_meow_face)]
-    fn handle_meow_face_bound(&mut self, data: Gd<VTubeStudioData>) {
-        self.handle_meow_face(data)
+    #[func]
+    fn get_model_offset_position(&self) -> Vector3 {
+        self.puppet3d.offset_position
     }
 
-    #[func(rename = handle_media_pipe)]
-    fn handle_media_pipe_bound(&mut self, projection: Projection, blend_shapes: Dictionary) {
-        self.handle_media_pipe(projection, blend_shapes);
+    #[func]
+    fn get_model_offset_rotation_degrees(&self) -> Vector3 {
+        self.puppet3d.offset_rotation_degrees
     }
-}
 
-impl VrmPuppet {
-    fn find_animation_player(&self) -> Option<Gd<AnimationPlayer>> {
-        if let Some(v) = self
-            .base
-            .find_child_ex(ANIM_PLAYER.into())
-            .owned(false)
-            .done()
-        {
-            v.try_cast::<AnimationPlayer>()
+    #[func(rename = capture_frame)]
+    fn capture_frame_bound(&self) -> PackedByteArray {
+        self.capture_frame()
+    }
+
+    /// Pin the model to `screen_rect`, so that it stays in place regardless of
+    /// tracking. Useful for compositing the model as an overlay.
+    #[func]
+    fn set_overlay_transform(&mut self, screen_rect: Rect2) {
+        self.puppet3d.overlay_rect = Some(screen_rect);
+
+        match self.skeleton.as_ref() {
+            Some(v) => self.apply_overlay_transform(
+                screen_rect,
+                skeleton_height(v, self.puppet3d.head_bone_id),
+            ),
+            None => self
+                .logger()
+                .debug("No skeleton found, the model will be pinned once loaded"),
+        }
+    }
+
+    /// Set the smoothing `factor` for head rotation and position, which drive the ik
+    /// targets. Defaults to [model::puppet::DEFAULT_TRACKING_SMOOTHING]. `0.0` applies
+    /// tracking data directly.
+    #[func]
+    fn set_tracking_smoothing(&mut self, factor: f32) {
+        self.smoothing
+            .set_factor(SmoothingKind::HeadRotation, factor);
+        self.smoothing
+            .set_factor(SmoothingKind::HeadPosition, factor);
+        self.puppet3d.smoothing = self.smoothing.factors();
+    }
+
+    /// Set the smoothing `factor` for the given kind of tracking data. `0.0` disables
+    /// smoothing while values closer to `1.0` smooth more heavily.
+    ///
+    /// Kinds are:
+    /// - `0`: head rotation
+    /// - `1`: head position
+    /// - `2`: eye gaze
+    /// - `3`: blend shapes
+    /// - `4`: lip sync
+    #[func]
+    fn set_smoothing(&mut self, kind: i64, factor: f32) -> Error {
+        match SmoothingKind::try_from(kind) {
+            Ok(v) => {
+                self.smoothing.set_factor(v, factor);
+                self.puppet3d.smoothing = self.smoothing.factors();
+                Error::OK
+            }
+            Err(e) => {
+                self.logger().error(format!("Unknown smoothing kind {e}"));
+                Error::ERR_INVALID_PARAMETER
+            }
+        }
+    }
+
+    /// Set the factor for converting incoming VTubeStudio positions into puppet units.
+    /// Larger models need larger values. Defaults to `0.02`.
+    #[func]
+    fn set_position_scale(&mut self, position_scale: f32) {
+        self.position_scale = position_scale;
+    }
+
+    #[func]
+    fn get_position_scale(&self) -> f32 {
+        self.position_scale
+    }
+
+    /// Ignore axes of incoming head data. Nonzero components of `rotation_locks` and
+    /// `position_locks` zero out that axis after smoothing is applied.
+    #[func]
+    fn set_axis_locks(&mut self, rotation_locks: Vector3i, position_locks: Vector3i) {
+        self.axis_locks = AxisLocks {
+            rotation: rotation_locks,
+            position: position_locks,
+        };
+    }
+
+    /// Apply raw tracking values directly, skipping smoothing and decay. Useful for
+    /// hitting a precise pose while calibrating. Previous settings apply again once disabled.
+    #[func]
+    fn set_calibration_mode(&mut self, enabled: bool) {
+        self.calibration_mode = enabled;
+        self.smoothing.bypass = enabled;
+    }
+
+    #[func]
+    fn get_calibration_mode(&self) -> bool {
+        self.calibration_mode
+    }
+
+    /// Use the latest head pose from each tracker as the neutral pose, so that the
+    /// puppet is centered while the user sits straight. Offsets are stored per tracker.
+    #[func]
+    fn calibrate(&mut self) -> Error {
+        if self.calibration.capture() {
+            Error::OK
         } else {
-            None
+            self.logger()
+                .error("No tracking data received yet, unable to calibrate");
+            Error::ERR_UNAVAILABLE
         }
     }
 
-    fn create_armature(&self, armature_name: &str, bone_name: &str) -> Option<Gd<Node3D>> {
-        let skeleton = self.skeleton.as_ref().unwrap();
+    /// Forget neutral poses captured with [VrmPuppet::calibrate].
+    #[func]
+    fn clear_calibration(&mut self) {
+        self.calibration.clear();
+    }
 
-        let bone_idx = skeleton.find_bone(bone_name.into());
-        if bone_idx < 0 {
-            return None;
+    /// Only apply the expression with the given `name` from tracking data. All
+    /// other expressions are zeroed out until [VrmPuppet::clear_solo_expression] is called.
+    #[func]
+    fn set_solo_expression(&mut self, name: GodotString) {
+        let name = name.to_string().to_lowercase();
+        if !self.expression_mappings.contains_key(&name) {
+            self.logger().warn(format!(
+                "Soloing unknown expression {name}, nothing will be applied"
+            ));
         }
 
-        let mut tx = skeleton.get_bone_global_pose(bone_idx);
-        tx.origin = self.base.to_global(tx.origin);
+        self.solo_expression = Some(name);
+    }
 
-        let mut armature = Node3D::new_alloc();
-        armature.set_name(armature_name.into());
-        armature.set_transform(tx);
+    /// Resume applying all expressions from tracking data.
+    #[func]
+    fn clear_solo_expression(&mut self) {
+        self.solo_expression = None;
+    }
 
-        Some(armature)
+    /// Toggle mesh visibility according to the VRM first-person mesh annotations.
+    /// Models default to third-person.
+    #[func]
+    pub fn set_first_person(&mut self, enabled: bool) {
+        for mesh in self.first_person_only_meshes.iter_mut() {
+            mesh.set_visible(enabled);
+        }
+        for mesh in self.third_person_only_meshes.iter_mut() {
+            mesh.set_visible(!enabled);
+        }
+    }
+
+    // #[func]
+    // fn get_vrm_type(&self) -> VrmType {
+    //     self.vrm_puppet.vrm_type.into()
+    // }
+
+    // #[func]
+    // fn set_vrm_type(&mut self, vrm_type: VrmType) {
+    //     self.vrm_puppet.vrm_type = vrm_type.into();
+    // }
+
+    #[func(rename = handle_open_see_face)]
+    fn handle_open_see_face_bound(&mut self, data: Dictionary) {
+        self.handle_open_see_face(data);
+    }
+
+    #[func(rename = handle_i_facial_mocap)]
+    fn handle_i_facial_mocap_bound(&mut self, data: Gd<IFacialMocapData>) {
+        self.handle_i_facial_mocap(data);
+    }
+
+    #[func(rename = handle_vtube_studio)]
+    fn handle_vtube_studio_bound(&mut self, data: Gd<VTubeStudioData>) {
+        self.handle_vtube_studio(data);
+    }
+
+    #[func(rename = handle_meow_face)]
+    fn handle_meow_face_bound(&mut self, data: Gd<VTubeStudioData>) {
+        self.handle_meow_face(data)
+    }
+
+    #[func(rename = handle_media_pipe)]
+    fn handle_media_pipe_bound(&mut self, data: Gd<MediaPipeData>) {
+        self.handle_media_pipe(data);
     }
 }
 
-impl Puppet for VrmPuppet {
-    fn logger(&self) -> Logger {
-        self.logger.bind().clone()
+impl VrmPuppet {
+    /// Set up everything needed for tracking from the managed node. Shared by
+    /// `ready` and [VrmPuppet::reload_model].
+    fn load(&mut self) {
+        let logger = self.logger();
+
+        logger.debug("Loading model");
+
+        match self.find_skeleton(&self.base) {
+            Some(v) => {
+                let _ = self.skeleton.replace(v);
+            }
+            None => {
+                self.fail_load("Unable to find skeleton, bailing out early!");
+                return;
+            }
+        }
+
+        let skeleton = self.skeleton.clone().unwrap();
+
+        self.puppet3d.head_bone_id = skeleton.find_bone(self.puppet3d.head_bone.clone().into());
+        if self.puppet3d.head_bone_id < 0 {
+            self.fail_load("No head bone found!");
+            return;
+        }
+
+        // TODO init skeleton bone transforms from config
+
+        // This must be done after loading the user's custom rest pose
+        for i in 0..skeleton.get_bone_count() {
+            self.puppet3d
+                .initial_bone_poses
+                .insert(i, skeleton.get_bone_pose(i));
+        }
+
+        let mut ik_targets_3d = IkTargets3d::default();
+        // TODO these are all hardcoded, maybe pull values from elsewhere?
+        if let v @ Some(_) = self.create_armature("HeadArmature", "Head") {
+            ik_targets_3d.head = v;
+
+            let tx = bone_global_transform(&skeleton, skeleton.find_bone("Head".into()));
+            ik_targets_3d.head_starting_transform = tx;
+        }
+        if let v @ Some(_) = self.create_armature("LeftHandArmature", "LeftHand") {
+            ik_targets_3d.left_hand = v;
+
+            let tx = bone_global_transform(&skeleton, skeleton.find_bone("LeftHand".into()));
+            ik_targets_3d.left_hand_starting_transform = tx;
+        }
+        if let v @ Some(_) = self.create_armature("RightHandArmature", "RightHand") {
+            ik_targets_3d.right_hand = v;
+
+            let tx = bone_global_transform(&skeleton, skeleton.find_bone("RightHand".into()));
+            ik_targets_3d.right_hand_starting_transform = tx;
+        }
+        if let v @ Some(_) = self.create_armature("HipsArmature", "Hips") {
+            ik_targets_3d.hips = v;
+
+            let tx = bone_global_transform(&skeleton, skeleton.find_bone("Hips".into()));
+            ik_targets_3d.hips_starting_transform = tx;
+        }
+        if let v @ Some(_) = self.create_armature("LeftFootArmature", "LeftFoot") {
+            ik_targets_3d.left_foot = v;
+        }
+        if let v @ Some(_) = self.create_armature("RightFootArmature", "RightFoot") {
+            ik_targets_3d.right_foot = v;
+        }
+        self.ik_targets_3d = Some(Gd::new(ik_targets_3d));
+
+        populate_blend_shape_mappings(
+            &mut self.blend_shape_mappings,
+            &find_mesh_instances(&skeleton),
+        );
+        for mapping in self.blend_shape_mappings.values_mut() {
+            mapping.cache();
+        }
+        if let Some(v) = self.find_animation_player() {
+            populate_and_modify_expression_mappings(&mut self.expression_mappings, &v);
+        } else {
+            error!("Unable to find Animation Player, blend shapes will not work!");
+        }
+        self.lip_sync_expression = lip_sync_expression(&self.expression_mappings);
+
+        let vrm_meta = match self
+            .managed_node()
+            .get(VRM_META.into())
+            .try_to::<Gd<Resource>>()
+        {
+            Ok(v) => v,
+            Err(e) => {
+                self.fail_load(format!("Unable to get vrm metadata, bailing out! {e:?}"));
+                return;
+            }
+        };
+        self.check_model_facing(&skeleton);
+        self.apply_model_facing(self.puppet3d.model_facing);
+        self.initial_transform = self.base.get_transform();
+        self.base.set_transform(offset_transform(
+            self.initial_transform,
+            self.puppet3d.offset_position,
+            self.puppet3d.offset_rotation_degrees,
+        ));
+        if let Some(rect) = self.puppet3d.overlay_rect {
+            self.apply_overlay_transform(
+                rect,
+                skeleton_height(&skeleton, self.puppet3d.head_bone_id),
+            );
+        }
+
+        self.populate_first_person_meshes(&vrm_meta);
+        self.set_first_person(false);
+        self.vrm_meta = Some(vrm_meta);
+
+        self.vrm_features = if is_perfect_sync(&self.expression_mappings) {
+            self.perfect_sync_mappings = perfect_sync_mappings(self.blend_shape_mappings.keys());
+            VrmFeatures::PerfectSync
+        } else {
+            let bone_map = self.humanoid_bone_map();
+
+            VrmFeatures::Base {
+                left_eye_id: skeleton
+                    .find_bone(humanoid_bone_name(bone_map.as_ref(), L_EYE).into()),
+                right_eye_id: skeleton
+                    .find_bone(humanoid_bone_name(bone_map.as_ref(), R_EYE).into()),
+            }
+        };
+        logger.debug(format!("Using vrm features {:?}", self.vrm_features));
+
+        // if self.a_pose() != Error::OK {
+        //     logger.error("Unable to a-pose");
+        // }
+
+        self.tracking_ready = true;
+        // Listeners usually call back into the puppet, which is still borrowed here
+        self.base.call_deferred(
+            "emit_signal".into(),
+            &[StringName::from("puppet_ready").to_variant()],
+        );
     }
 
-    fn managed_node(&self) -> Gd<Node> {
-        match self.base.get_child(0) {
+    /// Record why `ready` bailed out early and notify listeners.
+    fn fail_load(&mut self, reason: impl Into<GodotString>) {
+        let reason = reason.into();
+
+        self.logger().error(reason.to_string());
+        self.load_error = reason.clone();
+        // Deferred for the same reason as `puppet_ready`
+        self.base.call_deferred(
+            "emit_signal".into(),
+            &[
+                StringName::from("puppet_load_failed").to_variant(),
+                reason.to_variant(),
+            ],
+        );
+    }
+
+    /// Stop the current expression test and reset the tested expression.
+    fn stop_expression_test(&mut self) {
+        if let Some(test) = self.expression_test.take() {
+            self.set_expression(&test.name, 0.0);
+        }
+    }
+
+    /// Set an expression directly, skipping all processing.
+    fn set_expression(&mut self, name: &str, value: f32) {
+        let mappings = match self.expression_mappings.get(name) {
             Some(v) => v,
-            None => {
-                self.logger()
-                    .error("Unable to get managed node, this is a major error!");
+            None => return,
+        };
 
-                panic!("Bailing out!");
+        for key in mappings {
+            self.decaying_blend_shapes.remove(key);
+            if let Some(mapping) = self.blend_shape_mappings.get_mut(key) {
+                mapping.set_value(value);
             }
         }
     }
-}
 
-// NOTE we're using a slight hack to apply blend shapes as fast as possible
-// gdext classes are not Sync, but as long as they are created/destroyed in the
-// same thread, they can be used. Thus, we can find the mesh instance from the
-// instance id and modify it in the thread
-//
-// This does mean that the code is extremely not DRY
-impl Puppet3d for VrmPuppet {
-    fn handle_i_facial_mocap(&mut self, data: Gd<IFacialMocapData>) {
-        let data = data.bind();
-        let skeleton = self.skeleton.as_mut().unwrap();
+    /// The expression decay time constant, or `0.0` while decay is disabled.
+    fn active_expression_decay(&self) -> f32 {
+        if self.calibration_mode {
+            0.0
+        } else {
+            self.expression_decay
+        }
+    }
+
+    /// Advance every decaying blend shape by `delta` seconds. Blend shapes stop
+    /// decaying once they are close enough to their target.
+    fn decay_blend_shapes(&mut self, delta: f32) {
+        let expression_decay = self.active_expression_decay();
+        let blend_shape_mappings = &mut self.blend_shape_mappings;
+
+        self.decaying_blend_shapes.retain(|key, target| {
+            let mapping = match blend_shape_mappings.get_mut(key) {
+                Some(v) => v,
+                None => return false,
+            };
 
-        if let Some(ik) = self.ik_targets_3d.as_mut() {
-            let rotation = Vector3::new(data.rotation.x, data.rotation.y, data.rotation.z).to_variant();
-            if let Some(v) = ik.bind_mut().head.as_mut() {
-                v.call_deferred("set_rotation_degrees".into(), &[rotation.clone()]);
+            let value = decay_value(mapping.value(), *target, delta, expression_decay);
+            if (value - *target).abs() < DECAY_EPSILON {
+                mapping.set_value(*target);
+                false
+            } else {
+                mapping.set_value(value);
+                true
             }
-            let mut ik = ik.bind_mut();
+        });
+    }
+
+    /// The VRM humanoid bone mapping from the vrm metadata, if any.
+    fn humanoid_bone_map(&self) -> Option<Gd<BoneMap>> {
+        self.vrm_meta
+            .as_ref()?
+            .get(HUMANOID_BONE_MAPPING.into())
+            .try_to::<Gd<BoneMap>>()
+            .ok()
+    }
+
+    /// Log that tracking data was received without a skeleton. Only logs once
+    /// to avoid flooding the logs, since tracking data is received every frame.
+    fn log_missing_skeleton(&mut self) {
+        if !self.missing_skeleton_logged {
+            self.logger()
+                .error("Received tracking data without a skeleton, ignoring tracking data");
+            self.missing_skeleton_logged = true;
+        }
+    }
+
+    /// Rotate an eye bone from its rest pose by `rotation`, in degrees. See
+    /// [super::rotate_eye].
+    ///
+    /// # Returns
+    /// The clamped rotation, in degrees, or zero if there is no skeleton.
+    fn rotate_eye(&mut self, bone_id: i32, rotation: Vector3) -> Vector3 {
+        match self.skeleton.as_mut() {
+            Some(skeleton) => rotate_eye(skeleton, bone_id, rotation),
+            None => Vector3::ZERO,
+        }
+    }
+
+    /// Rotate the head ik target to `rotation`, in degrees, and move the head and
+    /// hand ik targets by `offset` from their starting positions.
+    fn apply_head_ik(&mut self, rotation: Option<Vector3>, offset: Option<Vector3>) {
+        let ik = match self.ik_targets_3d.as_mut() {
+            Some(v) => v,
+            None => return,
+        };
+        let mut ik = ik.bind_mut();
+
+        if let Some(rotation) = rotation {
+            let rotation = self
+                .axis_locks
+                .rotation(self.smoothing.head_rotation(rotation));
+            if let Some(v) = ik.head.as_mut() {
+                v.call_deferred("set_rotation_degrees".into(), &[rotation.to_variant()]);
+            }
+
+            self.applied_pose.head_rotation = rotation;
+        }
 
+        if let Some(offset) = offset {
+            let offset = self
+                .axis_locks
+                .position(self.smoothing.head_position(offset));
             let head_origin = ik.head_starting_transform.origin;
             if let Some(v) = ik.head.as_mut() {
                 v.call_deferred(
                     "set_position".into(),
-                    &[(head_origin + (data.position)).to_variant()],
+                    &[(head_origin + offset).to_variant()],
                 );
             }
+
             let left_hand_origin = ik.left_hand_starting_transform.origin;
             if let Some(v) = ik.left_hand.as_mut() {
                 v.call_deferred(
                     "set_position".into(),
-                    &[(left_hand_origin + (data.position)).to_variant()],
+                    &[(left_hand_origin + offset).to_variant()],
                 );
             }
+
             let right_hand_origin = ik.right_hand_starting_transform.origin;
             if let Some(v) = ik.right_hand.as_mut() {
                 v.call_deferred(
                     "set_position".into(),
-                    &[(right_hand_origin + (data.position)).to_variant()],
+                    &[(right_hand_origin + offset).to_variant()],
                 );
             }
+
+            self.applied_pose.head_position = offset;
         }
-        data.blend_shapes.par_iter().for_each(|(k, v)| {
-            if let Some(mappings) = self.expression_mappings.get(&k.to_lowercase()) {
-                for mapping in mappings {
-                    if let Some(mapping) = self.blend_shape_mappings.get(mapping) {
-                        Gd::<MeshInstance3D>::from_instance_id(InstanceId::from_i64(
-                            mapping.mesh_id,
-                        ))
-                        .set_indexed(NodePath::from(&mapping.blend_shape_path), v.to_variant());
-                    }
-                }
+    }
+
+    /// Drive the hand and hips ik targets from MediaPipe world landmarks. Face-only
+    /// data has no landmarks and leaves the ik targets untouched.
+    ///
+    /// Hands are rotated so that their y axis points along the fingers, matching
+    /// humanoid bones.
+    fn apply_media_pipe_landmarks(&mut self, data: &MediaPipeData) {
+        let ik = match self.ik_targets_3d.as_mut() {
+            Some(v) => v,
+            None => return,
+        };
+        let mut ik = ik.bind_mut();
+
+        let landmarks = data.pose_landmarks.as_slice();
+        if landmarks.len() >= MP_POSE_LANDMARK_COUNT {
+            // World landmarks are relative to the hips, so hands are placed relative to the hips
+            let hips_origin = ik.hips_starting_transform.origin;
+            if let Some(v) = ik.left_hand.as_mut() {
+                v.call_deferred(
+                    "set_position".into(),
+                    &[
+                        (hips_origin + media_pipe_to_puppet_space(landmarks[MP_LEFT_WRIST]))
+                            .to_variant(),
+                    ],
+                );
+            }
+            if let Some(v) = ik.right_hand.as_mut() {
+                v.call_deferred(
+                    "set_position".into(),
+                    &[
+                        (hips_origin + media_pipe_to_puppet_space(landmarks[MP_RIGHT_WRIST]))
+                            .to_variant(),
+                    ],
+                );
             }
-        });
 
-        match &self.vrm_features {
-            VrmFeatures::Base {
-                left_eye_id,
-                right_eye_id,
-            } => {}
-            VrmFeatures::PerfectSync => {}
+            let yaw = hips_yaw(
+                media_pipe_to_puppet_space(landmarks[MP_LEFT_HIP]),
+                media_pipe_to_puppet_space(landmarks[MP_RIGHT_HIP]),
+            );
+            if let Some(v) = ik.hips.as_mut() {
+                v.call_deferred(
+                    "set_rotation".into(),
+                    &[Vector3::new(0.0, yaw, 0.0).to_variant()],
+                );
+            }
         }
-    }
 
-    fn handle_vtube_studio(&mut self, data: Gd<VTubeStudioData>) {
-        let data = data.bind();
-        let skeleton = self.skeleton.as_mut().unwrap();
+        if let (Some(v), Some(basis)) = (
+            ik.left_hand.as_mut(),
+            media_pipe_hand_basis(&data.left_hand_landmarks),
+        ) {
+            v.call_deferred("set_basis".into(), &[basis.to_variant()]);
+        }
+        if let (Some(v), Some(basis)) = (
+            ik.right_hand.as_mut(),
+            media_pipe_hand_basis(&data.right_hand_landmarks),
+        ) {
+            v.call_deferred("set_basis".into(), &[basis.to_variant()]);
+        }
+    }
 
-        if let Some(rotation) = data.rotation {
-            if let Some(ik) = self.ik_targets_3d.as_mut() {
-                let mut ik = ik.bind_mut();
+    /// Apply tracked expression values to every mapped blend shape. Falling values
+    /// are decayed in `process` if configured.
+    fn apply_expressions<I, K>(&mut self, expressions: I)
+    where
+        I: IntoIterator<Item = (K, f32)>,
+        K: AsRef<str>,
+    {
+        let expression_decay = self.active_expression_decay();
+
+        let mut expressions = expressions
+            .into_iter()
+            .map(|(k, v)| (k.as_ref().to_lowercase(), v))
+            .collect::<Vec<(String, f32)>>();
+        apply_blink_settings(
+            &mut expressions,
+            self.vrm_puppet.link_eye_blinks,
+            self.vrm_puppet.blink_threshold,
+        );
 
-                // Data comes in Unity ordering I think?
-                let rotation = Vector3::new(rotation.y, rotation.x, rotation.z);
+        for (name, value) in expressions {
+            let value = match &self.solo_expression {
+                Some(solo) if *solo != name => 0.0,
+                _ => value,
+            };
+            let value = self.smoothing.blend_shape(&name, value);
 
-                let head_rotation = ik.head_starting_transform.basis.to_euler(EulerOrder::YXZ);
-                if let Some(v) = ik.head.as_mut() {
-                    v.call_deferred(
-                        "set_rotation_degrees".into(),
-                        &[(rotation - head_rotation).to_variant()],
-                    );
+            // Perfect Sync data skips the VRM expressions when the mesh has the
+            // blend shape, falling back to the expressions otherwise
+            let mappings = match self.vrm_features {
+                VrmFeatures::PerfectSync => self.perfect_sync_mappings.get(&name),
+                VrmFeatures::Base { .. } => None,
+            }
+            .or_else(|| self.expression_mappings.get(&name));
+
+            if let Some(mappings) = mappings {
+                for key in mappings {
+                    let mapping = match self.blend_shape_mappings.get_mut(key) {
+                        Some(v) => v,
+                        None => continue,
+                    };
+
+                    // Falling values are faded out in `process` instead
+                    if expression_decay > 0.0 && value < mapping.value() {
+                        self.decaying_blend_shapes.insert(key.clone(), value);
+                    } else {
+                        self.decaying_blend_shapes.remove(key);
+                        mapping.set_value(value);
+                    }
                 }
             }
         }
-        if let Some(position) = data.position {
-            if let Some(ik) = self.ik_targets_3d.as_mut() {
-                let mut ik = ik.bind_mut();
+    }
 
-                let head_origin = ik.head_starting_transform.origin;
-                if let Some(v) = ik.head.as_mut() {
-                    v.call_deferred(
-                        "set_position".into(),
-                        &[(head_origin - (position * 0.02)).to_variant()],
-                    );
-                }
+    /// Read the VRM `firstPerson` mesh annotations from the vrm metadata and
+    /// sort the annotated meshes by when they should be visible.
+    ///
+    /// Each annotation is expected to be a [Dictionary] containing a `mesh` path
+    /// relative to the managed node and a `first_person_flag`. Models without
+    /// annotations are skipped.
+    fn populate_first_person_meshes(&mut self, vrm_meta: &Gd<Resource>) {
+        let logger = self.logger();
 
-                let left_hand_origin = ik.left_hand_starting_transform.origin;
-                if let Some(v) = ik.left_hand.as_mut() {
-                    v.call_deferred(
-                        "set_position".into(),
-                        &[(left_hand_origin - (position * 0.02)).to_variant()],
-                    );
-                }
+        let annotations = match vrm_meta
+            .get(FIRST_PERSON_MESH_ANNOTATIONS.into())
+            .try_to::<Array<Dictionary>>()
+        {
+            Ok(v) => v,
+            Err(_) => {
+                logger.debug("No first-person mesh annotations found, skipping");
+                return;
+            }
+        };
 
-                let right_hand_origin = ik.right_hand_starting_transform.origin;
-                if let Some(v) = ik.right_hand.as_mut() {
-                    v.call_deferred(
-                        "set_position".into(),
-                        &[(right_hand_origin - (position * 0.02)).to_variant()],
-                    );
+        for annotation in annotations.iter_shared() {
+            let path = match annotation.get("mesh").map(|v| v.try_to::<NodePath>()) {
+                Some(Ok(v)) => v,
+                _ => {
+                    logger.error(format!("Invalid first-person annotation {annotation:?}"));
+                    continue;
                 }
+            };
+            let flag = annotation
+                .get("first_person_flag")
+                .map(|v| v.stringify().to_string())
+                .unwrap_or_default();
+
+            let mesh = match self
+                .get_nested_node_or_null(path.clone())
+                .and_then(|v| v.try_cast::<Node3D>())
+            {
+                Some(v) => v,
+                None => {
+                    logger.error(format!("Unable to find first-person mesh at {path}"));
+                    continue;
+                }
+            };
+
+            match first_person_flag(&flag) {
+                FirstPersonFlag::FirstPersonOnly => self.first_person_only_meshes.push(mesh),
+                FirstPersonFlag::ThirdPersonOnly => self.third_person_only_meshes.push(mesh),
+                FirstPersonFlag::Both => {}
             }
         }
+    }
+
+    fn find_animation_player(&self) -> Option<Gd<AnimationPlayer>> {
+        if let Some(v) = self
+            .base
+            .find_child_ex(ANIM_PLAYER.into())
+            .owned(false)
+            .done()
+        {
+            v.try_cast::<AnimationPlayer>()
+        } else {
+            None
+        }
+    }
+
+    fn create_armature(&self, armature_name: &str, bone_name: &str) -> Option<Gd<Node3D>> {
+        let skeleton = self.skeleton.as_ref()?;
+
+        let bone_idx = skeleton.find_bone(bone_name.into());
+        if bone_idx < 0 {
+            return None;
+        }
+
+        let tx = bone_global_transform(skeleton, bone_idx);
+
+        let mut armature = Node3D::new_alloc();
+        armature.set_name(armature_name.into());
+        armature.set_transform(tx);
+
+        Some(armature)
+    }
+}
+
+impl Puppet for VrmPuppet {
+    fn logger(&self) -> Logger {
+        self.logger.bind().clone()
+    }
+
+    fn try_managed_node(&self) -> Option<Gd<Node>> {
+        self.base.get_child(0)
+    }
+}
+
+// NOTE all Godot objects, including the meshes cached in each [BlendShapeMapping],
+// must only be touched from the thread that owns the puppet. gdext classes are
+// not Sync, so blend shapes are applied serially. If this ever needs to be
+// parallelized, only compute values on other threads and apply them here
+//
+// This does mean that the code is extremely not DRY
+impl Puppet3d for VrmPuppet {
+    fn handle_i_facial_mocap(&mut self, data: Gd<IFacialMocapData>) {
+        if !self.tracking_ready {
+            return;
+        }
+        self.stop_expression_test();
+
+        let data = data.bind();
+
+        self.tracking_quality
+            .record(Instant::now(), Some(data.rotation * DEG_TO_RAD));
+        let (rotation, position) = self.calibration.apply(
+            Tracker::IFacialMocap,
+            Some(data.rotation),
+            Some(data.position),
+        );
+        self.apply_head_ik(rotation, position);
+        self.apply_expressions(data.blend_shapes.iter().map(|(k, v)| (k, *v)));
+    }
+
+    fn handle_vtube_studio(&mut self, data: Gd<VTubeStudioData>) {
+        if !self.tracking_ready {
+            return;
+        }
+        self.stop_expression_test();
+
+        let data = data.bind();
+
+        self.tracking_quality.record(
+            Instant::now(),
+            data.rotation
+                .filter(|_| data.face_found.unwrap_or(true))
+                .map(|v| v * DEG_TO_RAD),
+        );
+
+        let head_rotation = self
+            .ik_targets_3d
+            .as_ref()
+            .map(|v| {
+                v.bind()
+                    .head_starting_transform
+                    .basis
+                    .orthonormalized()
+                    .to_euler(EulerOrder::YXZ)
+            })
+            .unwrap_or_default();
+        let (rotation, position) =
+            self.calibration
+                .apply(Tracker::VTubeStudio, data.rotation, data.position);
+
+        self.apply_head_ik(
+            // Data comes in Unity ordering I think?
+            rotation.map(|v| Vector3::new(v.y, v.x, v.z) - head_rotation),
+            position.map(|v| -(v * self.position_scale)),
+        );
         if let Some(blend_shapes) = &data.blend_shapes {
-            blend_shapes.par_iter().for_each(|v| {
-                if let Some(mappings) = self.expression_mappings.get(&v.k.to_lowercase()) {
-                    for mapping in mappings {
-                        if let Some(mapping) = self.blend_shape_mappings.get(mapping) {
-                            Gd::<MeshInstance3D>::from_instance_id(InstanceId::from_i64(
-                                mapping.mesh_id,
-                            ))
-                            .set_indexed(
-                                NodePath::from(&mapping.blend_shape_path),
-                                v.v.to_variant(),
-                            );
-                        }
-                    }
-                }
-            });
+            self.apply_expressions(blend_shapes.iter().map(|v| (&v.k, v.v)));
         }
 
-        match &self.vrm_features {
+        match self.vrm_features {
             VrmFeatures::Base {
                 left_eye_id,
                 right_eye_id,
-            } => {}
+            } => {
+                if let Some(v) = data.eye_left {
+                    let rotation = self.smoothing.left_eye(v);
+                    self.applied_pose.left_eye = self.rotate_eye(left_eye_id, rotation);
+                }
+                if let Some(v) = data.eye_right {
+                    let rotation = self.smoothing.right_eye(v);
+                    self.applied_pose.right_eye = self.rotate_eye(right_eye_id, rotation);
+                }
+            }
+            // Perfect sync models look around with blend shapes instead
             VrmFeatures::PerfectSync => {}
         }
     }
@@ -738,41 +1734,473 @@ impl Puppet3d for VrmPuppet {
         self.handle_vtube_studio(data);
     }
 
-    fn handle_media_pipe(&mut self, projection: Projection, blend_shapes: Dictionary) {
-        let skeleton = self.skeleton.as_mut().unwrap();
+    fn handle_open_see_face(&mut self, data: Dictionary) {
+        if !self.tracking_ready {
+            return;
+        }
+        self.stop_expression_test();
+
+        let rotation = match data
+            .get("quaternion")
+            .and_then(|v| v.try_to::<Quaternion>().ok())
+        {
+            Some(v) => Basis::from_quat(v).to_euler(EulerOrder::YXZ),
+            None => return,
+        };
+        let face_found = data
+            .get("success")
+            .and_then(|v| v.try_to::<bool>().ok())
+            .unwrap_or(true);
+
+        self.tracking_quality
+            .record(Instant::now(), face_found.then_some(rotation));
+        let (rotation, _) = self
+            .calibration
+            .apply(Tracker::OpenSeeFace, Some(rotation), None);
+        self.apply_head_ik(rotation.map(|v| v / DEG_TO_RAD), None);
+    }
+
+    fn handle_media_pipe(&mut self, data: Gd<MediaPipeData>) {
+        if !self.tracking_ready {
+            return;
+        }
+        self.stop_expression_test();
 
-        let tx = Transform3D::from_projection(projection.inverse());
+        let skeleton = match self.skeleton.as_mut() {
+            Some(v) => v,
+            None => {
+                self.log_missing_skeleton();
+                return;
+            }
+        };
 
-        skeleton.set_bone_pose_rotation(self.puppet3d.head_bone_id, tx.basis.to_quat());
+        let data = data.bind();
 
-        let blend_shapes: HashMap<String, f32, RandomState> = HashMap::from_iter(
-            blend_shapes
-                .iter_shared()
-                .map(|(k, v)| (k.to_string(), v.to::<f32>())),
+        let tx = Transform3D::from_projection(data.projection.inverse());
+        let raw_rotation = tx.basis.to_euler(EulerOrder::YXZ);
+        self.tracking_quality
+            .record(Instant::now(), Some(raw_rotation));
+        let (raw_rotation, _) =
+            self.calibration
+                .apply(Tracker::MediaPipe, Some(raw_rotation), None);
+        let rotation = self.axis_locks.rotation(
+            self.smoothing
+                .head_rotation(raw_rotation.unwrap_or_default()),
         );
 
-        blend_shapes.par_iter().for_each(|(name, value)| {
-            if let Some(mappings) = self.expression_mappings.get(&name.to_lowercase()) {
-                for mapping in mappings {
-                    if let Some(mapping) = self.blend_shape_mappings.get(mapping) {
-                        Gd::<MeshInstance3D>::from_instance_id(InstanceId::from_i64(
-                            mapping.mesh_id,
-                        ))
-                        .set_indexed(
-                            NodePath::from(&mapping.blend_shape_path),
-                            value.to_variant(),
-                        );
-                    }
-                }
+        skeleton
+            .set_bone_pose_rotation(self.puppet3d.head_bone_id, Quaternion::from_euler(rotation));
+        self.applied_pose.head_rotation = rotation;
+
+        self.apply_expressions(data.blend_shapes.iter().map(|(k, v)| (k, *v)));
+        self.apply_media_pipe_landmarks(&data);
+    }
+}
+
+/// Link and snap the ARKit eye blinks in `expressions`, if present. Linked blinks
+/// both use the larger of the two values. Blinks above `threshold` are snapped to
+/// `1.0`, a `threshold` of `0.0` disables snapping.
+fn apply_blink_settings(expressions: &mut [(String, f32)], link: bool, threshold: f32) {
+    let left = expressions.iter().position(|(k, _)| k == "eyeblinkleft");
+    let right = expressions.iter().position(|(k, _)| k == "eyeblinkright");
+
+    if link {
+        if let (Some(left), Some(right)) = (left, right) {
+            let value = expressions[left].1.max(expressions[right].1);
+            expressions[left].1 = value;
+            expressions[right].1 = value;
+        }
+    }
+
+    if threshold > 0.0 {
+        for idx in [left, right].into_iter().flatten() {
+            if expressions[idx].1 > threshold {
+                expressions[idx].1 = 1.0;
             }
-        });
+        }
+    }
+}
 
-        match &self.vrm_features {
-            VrmFeatures::Base {
-                left_eye_id,
-                right_eye_id,
-            } => {}
-            VrmFeatures::PerfectSync => {}
+/// Move `current` towards `target`. Rising values are applied immediately while
+/// falling values approach `target` exponentially over the `time_constant`.
+fn decay_value(current: f32, target: f32, delta: f32, time_constant: f32) -> f32 {
+    if target >= current || time_constant <= 0.0 {
+        return target;
+    }
+
+    target + (current - target) * (-delta / time_constant).exp()
+}
+
+/// Convert a MediaPipe world landmark into puppet space.
+///
+/// MediaPipe world landmarks are in meters with the origin between the hips, `+y`
+/// pointing down, `-z` pointing towards the camera, and `+x` pointing towards the
+/// subject's left. Puppets face the camera along `+z` and have their left side on `+x`.
+fn media_pipe_to_puppet_space(landmark: Vector3) -> Vector3 {
+    Vector3::new(landmark.x, -landmark.y, -landmark.z)
+}
+
+/// The rotation of the hips around the up axis, in radians, given both hips in
+/// puppet space. `0.0` when facing the camera.
+fn hips_yaw(left_hip: Vector3, right_hip: Vector3) -> f32 {
+    let dir = left_hip - right_hip;
+
+    (-dir.z).atan2(dir.x)
+}
+
+/// The orientation of a hand given its MediaPipe world `landmarks`, with the y axis
+/// pointing from the wrist along the fingers and the z axis perpendicular to the palm.
+/// `None` if the hand was not tracked.
+fn media_pipe_hand_basis(landmarks: &[Vector3]) -> Option<Basis> {
+    if landmarks.len() < MP_HAND_LANDMARK_COUNT {
+        return None;
+    }
+
+    let wrist = media_pipe_to_puppet_space(landmarks[MP_HAND_WRIST]);
+    let up = (media_pipe_to_puppet_space(landmarks[MP_HAND_MIDDLE_MCP]) - wrist).normalized();
+    let side = media_pipe_to_puppet_space(landmarks[MP_HAND_INDEX_MCP])
+        - media_pipe_to_puppet_space(landmarks[MP_HAND_PINKY_MCP]);
+    let normal = side.cross(up).normalized();
+
+    Some(Basis::from_cols(up.cross(normal), up, normal))
+}
+
+/// The parts of a [Skeleton3D] needed for finding the global transform of a bone.
+/// Allows for bone transforms to be checked without a Godot scene, e.g. in tests.
+trait BoneHierarchy {
+    /// The global transform of the skeleton itself.
+    fn global_transform(&self) -> Transform3D;
+
+    /// The parent of the bone, or `-1` for root bones.
+    fn bone_parent(&self, bone_idx: i32) -> i32;
+
+    /// The pose of the bone relative to its parent.
+    fn bone_pose(&self, bone_idx: i32) -> Transform3D;
+}
+
+impl BoneHierarchy for Gd<Skeleton3D> {
+    fn global_transform(&self) -> Transform3D {
+        self.get_global_transform()
+    }
+
+    fn bone_parent(&self, bone_idx: i32) -> i32 {
+        self.get_bone_parent(bone_idx)
+    }
+
+    fn bone_pose(&self, bone_idx: i32) -> Transform3D {
+        self.get_bone_pose(bone_idx)
+    }
+}
+
+/// The global transform of a bone, including any scale on the skeleton or its parents.
+fn bone_global_transform<S: BoneHierarchy>(skeleton: &S, bone_idx: i32) -> Transform3D {
+    let mut pose = Transform3D::IDENTITY;
+    let mut idx = bone_idx;
+    while idx >= 0 {
+        pose = skeleton.bone_pose(idx) * pose;
+        idx = skeleton.bone_parent(idx);
+    }
+
+    skeleton.global_transform() * pose
+}
+
+/// An expression being animated by `test_expression`.
+#[derive(Debug)]
+struct ExpressionTest {
+    name: String,
+    duration: f32,
+    elapsed: f32,
+}
+
+/// The weight of a tested expression `elapsed` seconds into a test lasting `duration`
+/// seconds. Ramps linearly from `0.0` to `1.0` and back to `0.0`.
+fn expression_test_weight(elapsed: f32, duration: f32) -> f32 {
+    let t = (elapsed / duration).clamp(0.0, 1.0);
+
+    if t < 0.5 {
+        t * 2.0
+    } else {
+        (1.0 - t) * 2.0
+    }
+}
+
+/// The rotation applied on top of a bone's pose when a-posing.
+fn a_pose_adjustment(bone_name: &str) -> Quaternion {
+    match bone_name {
+        L_SHOULDER | R_SHOULDER => Quaternion::from_angle_axis(Vector3::LEFT, 0.34),
+        L_UPPER_ARM | R_UPPER_ARM => Quaternion::from_angle_axis(Vector3::RIGHT, 0.52),
+        _ => unreachable!("This should never happen!"),
+    }
+}
+
+/// The rotation that undoes any roll the lower arm picked up while a-posing, given
+/// its skeleton space orientation before (`rest`) and after (`posed`) the upper
+/// arm adjustment.
+///
+/// Bones point along their local y axis, so roll is the twist of the change in
+/// orientation around that axis.
+fn lower_arm_roll_correction(rest: Quaternion, posed: Quaternion) -> Quaternion {
+    let q = rest.inverse() * posed;
+    let twist = Quaternion::new(0.0, q.y, 0.0, q.w);
+    if twist.length_squared() < f32::EPSILON {
+        return Quaternion::default();
+    }
+
+    twist.normalized().inverse()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perfect_sync_requires_all_expressions() {
+        let mut mappings = PERFECT_SYNC_EXPRESSIONS
+            .iter()
+            .map(|v| (v.to_string(), vec![]))
+            .collect::<HashMap<String, Vec<String>>>();
+        mappings.insert("aa".to_string(), vec![]);
+        assert!(is_perfect_sync(&mappings));
+
+        mappings.remove("tongueout");
+        assert!(!is_perfect_sync(&mappings));
+    }
+
+    #[test]
+    fn lip_sync_prefers_aa() {
+        let mut mappings: HashMap<String, Vec<String>> = HashMap::new();
+        assert_eq!(lip_sync_expression(&mappings), None);
+
+        mappings.insert("a".to_string(), vec![]);
+        assert_eq!(lip_sync_expression(&mappings), Some("a"));
+
+        mappings.insert("aa".to_string(), vec![]);
+        assert_eq!(lip_sync_expression(&mappings), Some("aa"));
+    }
+
+    #[test]
+    fn first_person_flags_ignore_case() {
+        for flag in ["FirstPersonOnly", "firstPersonOnly"] {
+            assert_eq!(first_person_flag(flag), FirstPersonFlag::FirstPersonOnly);
+        }
+        for flag in ["ThirdPersonOnly", "thirdPersonOnly"] {
+            assert_eq!(first_person_flag(flag), FirstPersonFlag::ThirdPersonOnly);
+        }
+        for flag in ["Both", "auto", ""] {
+            assert_eq!(first_person_flag(flag), FirstPersonFlag::Both);
+        }
+    }
+
+    #[test]
+    fn blink_settings() {
+        let blinks = || {
+            vec![
+                ("eyeblinkleft".to_string(), 0.2),
+                ("eyeblinkright".to_string(), 0.85),
+                ("jawopen".to_string(), 0.9),
+            ]
+        };
+
+        let mut expressions = blinks();
+        apply_blink_settings(&mut expressions, false, 0.0);
+        assert_eq!(expressions, blinks());
+
+        let mut expressions = blinks();
+        apply_blink_settings(&mut expressions, true, 0.0);
+        assert_eq!(expressions[0].1, 0.85);
+        assert_eq!(expressions[1].1, 0.85);
+
+        let mut expressions = blinks();
+        apply_blink_settings(&mut expressions, false, 0.8);
+        assert_eq!(expressions[0].1, 0.2);
+        assert_eq!(expressions[1].1, 1.0);
+        assert_eq!(expressions[2].1, 0.9);
+
+        let mut expressions = blinks();
+        apply_blink_settings(&mut expressions, true, 0.8);
+        assert_eq!(expressions[0].1, 1.0);
+        assert_eq!(expressions[1].1, 1.0);
+    }
+
+    #[test]
+    fn perfect_sync_mappings_ignore_case() {
+        let names = [
+            "jawOpen",
+            "JawOpen",
+            "eyeBlinkLeft",
+            "Face.M_F00_000_00_Fcl_ALL_Joy",
+        ]
+        .map(String::from);
+
+        let mappings = perfect_sync_mappings(names.iter());
+
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings["jawopen"], vec!["jawOpen", "JawOpen"]);
+        assert_eq!(mappings["eyeblinkleft"], vec!["eyeBlinkLeft"]);
+    }
+
+    #[test]
+    fn a_pose_from_identity_rest_pose() {
+        let rest = Quaternion::default();
+
+        assert!((rest * a_pose_adjustment(L_SHOULDER))
+            .is_equal_approx(Quaternion::from_angle_axis(Vector3::LEFT, 0.34)));
+        assert!((rest * a_pose_adjustment(R_UPPER_ARM))
+            .is_equal_approx(Quaternion::from_angle_axis(Vector3::RIGHT, 0.52)));
+    }
+
+    /// Skeleton space orientations of a lower arm in a t-pose and after a-posing.
+    fn a_posed_lower_arm(lower_arm_rest: Quaternion) -> (Quaternion, Quaternion) {
+        // The left arm points along +x in a t-pose
+        let upper_arm = Quaternion::from_angle_axis(Vector3::BACK, -std::f32::consts::FRAC_PI_2);
+
+        (
+            upper_arm * lower_arm_rest,
+            upper_arm * a_pose_adjustment(L_UPPER_ARM) * lower_arm_rest,
+        )
+    }
+
+    #[test]
+    fn straight_lower_arm_has_no_roll() {
+        let (rest, posed) = a_posed_lower_arm(Quaternion::default());
+
+        assert!(lower_arm_roll_correction(rest, posed).is_equal_approx(Quaternion::default()));
+    }
+
+    #[test]
+    fn bent_lower_arm_roll_is_undone() {
+        let (rest, posed) = a_posed_lower_arm(Quaternion::from_angle_axis(Vector3::BACK, 0.4));
+        let correction = lower_arm_roll_correction(rest, posed);
+        assert!(!correction.is_equal_approx(Quaternion::default()));
+
+        let q = rest.inverse() * posed * correction;
+        let remaining_twist = Quaternion::new(0.0, q.y, 0.0, q.w).normalized();
+
+        assert!(remaining_twist.is_equal_approx(Quaternion::default()));
+    }
+
+    struct MockSkeleton {
+        transform: Transform3D,
+        /// Each bone's parent and pose, indexed by bone.
+        bones: Vec<(i32, Transform3D)>,
+    }
+
+    impl BoneHierarchy for MockSkeleton {
+        fn global_transform(&self) -> Transform3D {
+            self.transform
+        }
+
+        fn bone_parent(&self, bone_idx: i32) -> i32 {
+            self.bones[bone_idx as usize].0
+        }
+
+        fn bone_pose(&self, bone_idx: i32) -> Transform3D {
+            self.bones[bone_idx as usize].1
         }
     }
+
+    #[test]
+    fn bone_chain_on_scaled_root() {
+        let up = |y| Transform3D::new(Basis::IDENTITY, Vector3::new(0.0, y, 0.0));
+        let skeleton = MockSkeleton {
+            transform: Transform3D::new(
+                Basis::from_scale(Vector3::ONE * 2.0),
+                Vector3::new(1.0, 0.0, 0.0),
+            ),
+            bones: vec![
+                (-1, up(1.0)),
+                // Turned to the left, so children extend along -x
+                (
+                    0,
+                    Transform3D::new(
+                        Basis::from_euler(
+                            EulerOrder::YXZ,
+                            Vector3::new(0.0, 0.0, std::f32::consts::FRAC_PI_2),
+                        ),
+                        Vector3::new(0.0, 0.5, 0.0),
+                    ),
+                ),
+                (1, up(0.25)),
+            ],
+        };
+
+        let tx = bone_global_transform(&skeleton, 0);
+        assert!(tx.origin.is_equal_approx(Vector3::new(1.0, 2.0, 0.0)));
+
+        let tx = bone_global_transform(&skeleton, 2);
+        assert!(tx.origin.is_equal_approx(Vector3::new(0.5, 3.0, 0.0)));
+        assert!(tx.basis.scale().is_equal_approx(Vector3::ONE * 2.0));
+    }
+
+    #[test]
+    fn media_pipe_landmarks_to_puppet_space() {
+        // Above, in front of, and to the left of the hips
+        let v = media_pipe_to_puppet_space(Vector3::new(0.2, -0.5, -0.3));
+
+        assert!(v.is_equal_approx(Vector3::new(0.2, 0.5, 0.3)));
+    }
+
+    #[test]
+    fn hips_facing_camera() {
+        let yaw = hips_yaw(Vector3::new(0.1, 0.0, 0.0), Vector3::new(-0.1, 0.0, 0.0));
+
+        assert_eq!(yaw, 0.0);
+    }
+
+    #[test]
+    fn hips_turned() {
+        // Left hip moved back, right hip moved forward
+        let left_hip = Vector3::new(0.0, 0.0, -0.1);
+        let right_hip = Vector3::new(0.0, 0.0, 0.1);
+
+        let yaw = hips_yaw(left_hip, right_hip);
+        let rotated = Basis::from_euler(EulerOrder::YXZ, Vector3::new(0.0, yaw, 0.0))
+            * Vector3::new(0.1, 0.0, 0.0);
+
+        assert!(rotated.is_equal_approx(left_hip));
+    }
+
+    #[test]
+    fn media_pipe_hand_pointing_up() {
+        let mut landmarks = vec![Vector3::ZERO; MP_HAND_LANDMARK_COUNT];
+        // Fingers point up, which is -y for MediaPipe
+        landmarks[MP_HAND_MIDDLE_MCP] = Vector3::new(0.0, -0.1, 0.0);
+        landmarks[MP_HAND_INDEX_MCP] = Vector3::new(0.03, -0.1, 0.0);
+        landmarks[MP_HAND_PINKY_MCP] = Vector3::new(-0.03, -0.09, 0.0);
+
+        let basis = media_pipe_hand_basis(&landmarks).unwrap();
+
+        assert!((basis * Vector3::UP).is_equal_approx(Vector3::UP));
+        assert!((basis * Vector3::RIGHT).dot(Vector3::UP).abs() < 1e-5);
+        assert!((basis.determinant() - 1.0).abs() < 1e-5);
+        assert!(media_pipe_hand_basis(&landmarks[..MP_HAND_LANDMARK_COUNT - 1]).is_none());
+    }
+
+    #[test]
+    fn expression_test_ramps_up_and_down() {
+        assert_eq!(expression_test_weight(0.0, 2.0), 0.0);
+        assert_eq!(expression_test_weight(0.5, 2.0), 0.5);
+        assert_eq!(expression_test_weight(1.0, 2.0), 1.0);
+        assert_eq!(expression_test_weight(1.5, 2.0), 0.5);
+        assert_eq!(expression_test_weight(2.0, 2.0), 0.0);
+        assert_eq!(expression_test_weight(3.0, 2.0), 0.0);
+    }
+
+    #[test]
+    fn rising_expression_applies_immediately() {
+        assert_eq!(decay_value(0.2, 0.8, 0.016, 0.5), 0.8);
+    }
+
+    #[test]
+    fn falling_expression_decays() {
+        let v = decay_value(1.0, 0.0, 0.1, 0.5);
+
+        assert!(v > 0.0 && v < 1.0);
+        assert!(decay_value(v, 0.0, 0.1, 0.5) < v);
+    }
+
+    #[test]
+    fn no_decay_snaps_to_target() {
+        assert_eq!(decay_value(1.0, 0.0, 0.1, 0.0), 0.0);
+    }
 }
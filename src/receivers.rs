@@ -0,0 +1,346 @@
+pub mod i_facial_mocap;
+#[cfg(feature = "lip-sync")]
+pub mod lip_sync;
+pub mod open_see_face;
+pub mod vtube_studio;
+
+use std::{
+    net::UdpSocket,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use godot::{engine::global::Error, prelude::*};
+use log::{debug, error};
+
+use crate::{cli::Tracker, model::tracking_data::DEFAULT_ADDRESS};
+
+/// The handshake iFacialMocap expects before it starts sending data.
+const IFM_HANDSHAKE: &str = "iFacialMocap_sahuasouryya9218sauhuiayeta91555dy3719";
+/// iFacialMocap always sends data back to this port.
+const IFM_PORT: u16 = 49983;
+/// Maximum number of bytes kept from the first received frame.
+const MAX_SAMPLE_SIZE: usize = 4096;
+/// How often receive threads check if they should stop.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A long-running receiver for tracking data. Data is received on a separate thread
+/// and the latest frame is kept until it is polled.
+pub trait Receiver {
+    /// Create a receiver from an options [Dictionary]. Missing options use defaults.
+    fn create(options: Dictionary) -> Self
+    where
+        Self: Sized;
+
+    /// Start receiving data.
+    fn start(&mut self) -> Error;
+
+    /// Stop receiving data and wait for the receive thread to finish.
+    fn stop(&mut self) -> Error;
+
+    /// Take the latest received frame as a [Dictionary], if any.
+    fn poll(&mut self) -> Option<Dictionary>;
+}
+
+/// Read the `address` and `port` options of a receiver. Missing or invalid options
+/// fall back to [DEFAULT_ADDRESS] and `default_port`.
+fn address_and_port(options: &Dictionary, default_port: i32) -> (String, u16) {
+    let address = options
+        .get("address")
+        .and_then(|v| v.try_to::<GodotString>().ok())
+        .map(|v| v.to_string())
+        .filter(|v| !v.is_empty())
+        .unwrap_or(DEFAULT_ADDRESS.to_string());
+    let port = options
+        .get("port")
+        .and_then(|v| v.try_to::<i64>().ok())
+        .and_then(|v| u16::try_from(v).ok())
+        .unwrap_or(default_port as u16);
+
+    (address, port)
+}
+
+/// The receive thread of a [Receiver] and the latest frame it received. The thread
+/// is stopped when dropped.
+#[derive(Debug)]
+struct ReceiveThread<T> {
+    name: &'static str,
+    running: Arc<AtomicBool>,
+    latest: Arc<Mutex<Option<T>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<T> ReceiveThread<T> {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            running: Arc::new(AtomicBool::new(false)),
+            latest: Arc::new(Mutex::new(None)),
+            handle: None,
+        }
+    }
+
+    /// Whether the thread was started and not stopped yet. Logs an error if so,
+    /// since receivers can only be started once.
+    fn already_started(&self) -> bool {
+        if self.handle.is_some() {
+            error!("{} receiver is already running", self.name);
+        }
+
+        self.handle.is_some()
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Start receiving packets from `socket`, see [spawn_receive_loop]. Frames are
+    /// stored with [store_latest] into [ReceiveThread::latest].
+    fn start<F>(&mut self, socket: UdpSocket, handle_packet: F) -> Error
+    where
+        F: FnMut(&UdpSocket, Option<&[u8]>) -> bool + Send + 'static,
+    {
+        match spawn_receive_loop(self.name, socket, self.running.clone(), handle_packet) {
+            Ok(v) => {
+                self.handle = Some(v);
+                Error::OK
+            }
+            Err(e) => e,
+        }
+    }
+
+    /// Stop the thread and wait for it to finish.
+    fn stop(&mut self) -> Error {
+        stop_receive_loop(self.name, &self.running, &mut self.handle)
+    }
+
+    /// The latest frame, shared with the receive thread.
+    fn latest(&self) -> Arc<Mutex<Option<T>>> {
+        self.latest.clone()
+    }
+
+    /// Take the latest frame, if any was received since the last call.
+    fn take_latest(&self) -> Option<T> {
+        self.latest.lock().ok()?.take()
+    }
+}
+
+impl<T> Drop for ReceiveThread<T> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Replace the `latest` frame with `frame`.
+///
+/// # Returns
+/// `false` if the lock is poisoned, in which case the receive thread should stop.
+fn store_latest<T>(latest: &Mutex<Option<T>>, frame: T) -> bool {
+    match latest.lock() {
+        Ok(mut v) => {
+            v.replace(frame);
+            true
+        }
+        Err(e) => {
+            error!("{e}");
+            false
+        }
+    }
+}
+
+/// Receive packets from `socket` on a new thread until `running` is unset or
+/// `handle_packet` returns `false`.
+///
+/// `handle_packet` is called with each received packet, or `None` every
+/// [READ_TIMEOUT] if nothing was received, so that requests can be resent.
+fn spawn_receive_loop<F>(
+    name: &'static str,
+    socket: UdpSocket,
+    running: Arc<AtomicBool>,
+    mut handle_packet: F,
+) -> Result<JoinHandle<()>, Error>
+where
+    F: FnMut(&UdpSocket, Option<&[u8]>) -> bool + Send + 'static,
+{
+    if let Err(e) = socket.set_read_timeout(Some(READ_TIMEOUT)) {
+        error!("{e}");
+        return Err(Error::ERR_CANT_OPEN);
+    }
+
+    running.store(true, Ordering::Relaxed);
+
+    Ok(std::thread::spawn(move || {
+        let mut buf = vec![0; u16::MAX as usize];
+
+        while running.load(Ordering::Relaxed) {
+            let keep_running = match socket.recv(&mut buf) {
+                Ok(len) => handle_packet(&socket, Some(&buf[..len])),
+                Err(e) => match e.kind() {
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                        handle_packet(&socket, None)
+                    }
+                    _ => {
+                        error!("Unexpected error while receiving: {e}");
+                        false
+                    }
+                },
+            };
+            if !keep_running {
+                break;
+            }
+        }
+
+        running.store(false, Ordering::Relaxed);
+        debug!("{name} receiver stopped");
+    }))
+}
+
+/// Stop a thread started with [spawn_receive_loop] and wait for it to finish.
+fn stop_receive_loop(
+    name: &str,
+    running: &AtomicBool,
+    handle: &mut Option<JoinHandle<()>>,
+) -> Error {
+    running.store(false, Ordering::Relaxed);
+
+    match handle.take() {
+        Some(v) => match v.join() {
+            Ok(_) => Error::OK,
+            Err(e) => {
+                error!("{name} receiver panicked: {e:?}");
+                Error::ERR_BUG
+            }
+        },
+        None => Error::OK,
+    }
+}
+
+/// Build the request that MeowFace and VTubeStudio expect before they start
+/// sending data to the given `port`.
+fn ios_tracking_data_request(port: u16) -> String {
+    format!(
+        r#"{{"messageType":"iOSTrackingDataRequest","time":1.0,"sentBy":"vpuppr","ports":[{port}]}}"#
+    )
+}
+
+/// Briefly connect to a tracker and check if any data is received within
+/// `timeout_ms`.
+///
+/// A new socket is always used and is dropped before returning, so a running
+/// receiver is never touched. Trackers that send to a fixed port will report
+/// an error if that port is already bound by a running receiver.
+///
+/// # Returns
+/// A [Dictionary] containing:
+/// - `ok`: `bool`, whether any data was received
+/// - `error`: `String`, empty on success
+/// - `bytes_received`: `int`, the size of the first frame
+/// - `sample`: `PackedByteArray`, the first frame, truncated
+pub fn test_connection(tracker: &str, address: &str, port: u16, timeout_ms: u64) -> Dictionary {
+    let mut r = Dictionary::new();
+
+    match try_receive_frame(tracker, address, port, Duration::from_millis(timeout_ms)) {
+        Ok(frame) => {
+            r.insert("ok", true);
+            r.insert("error", GodotString::new());
+            r.insert("bytes_received", frame.len() as i64);
+            r.insert(
+                "sample",
+                PackedByteArray::from(&frame[..frame.len().min(MAX_SAMPLE_SIZE)]),
+            );
+        }
+        Err(e) => {
+            error!("{e}");
+
+            r.insert("ok", false);
+            r.insert("error", GodotString::from(e));
+            r.insert("bytes_received", 0);
+            r.insert("sample", PackedByteArray::new());
+        }
+    }
+
+    r
+}
+
+/// Open a socket for the given `tracker`, send its handshake if needed, and wait
+/// for a single frame.
+fn try_receive_frame(
+    tracker: &str,
+    address: &str,
+    port: u16,
+    timeout: Duration,
+) -> Result<Vec<u8>, String> {
+    let tracker = Tracker::from_str(tracker).map_err(|e| e.to_string())?;
+
+    let socket = match &tracker {
+        Tracker::MeowFace | Tracker::VTubeStudio => {
+            let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+            let local_port = socket.local_addr().map_err(|e| e.to_string())?.port();
+
+            socket
+                .send_to(
+                    ios_tracking_data_request(local_port).as_bytes(),
+                    (address, port),
+                )
+                .map_err(|e| format!("Unable to send request to {address}:{port}: {e}"))?;
+
+            socket
+        }
+        Tracker::IFacialMocap => {
+            let socket = UdpSocket::bind(("0.0.0.0", IFM_PORT)).map_err(|e| {
+                format!("Unable to bind port {IFM_PORT}, is a receiver already running? {e}")
+            })?;
+
+            socket
+                .send_to(IFM_HANDSHAKE.as_bytes(), (address, port))
+                .map_err(|e| format!("Unable to send request to {address}:{port}: {e}"))?;
+
+            socket
+        }
+        Tracker::OpenSeeFace => UdpSocket::bind((address, port)).map_err(|e| {
+            format!("Unable to bind {address}:{port}, is a receiver already running? {e}")
+        })?,
+        Tracker::MediaPipe | Tracker::Custom(_) => {
+            return Err(format!(
+                "Testing the connection is not supported for {}",
+                tracker.as_ref()
+            ));
+        }
+    };
+
+    debug!("Waiting for data from {}", tracker.as_ref());
+
+    let mut buf = vec![0; u16::MAX as usize];
+    let start = Instant::now();
+    loop {
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return Err(format!(
+                "No data received from {} within {}ms",
+                tracker.as_ref(),
+                timeout.as_millis()
+            ));
+        }
+
+        socket
+            .set_read_timeout(Some(remaining))
+            .map_err(|e| e.to_string())?;
+
+        match socket.recv(&mut buf) {
+            Ok(0) => continue,
+            Ok(len) => {
+                buf.truncate(len);
+                return Ok(buf);
+            }
+            Err(e) => match e.kind() {
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => continue,
+                _ => return Err(format!("Unexpected error while receiving: {e}")),
+            },
+        }
+    }
+}
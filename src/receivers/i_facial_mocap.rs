@@ -0,0 +1,153 @@
+use std::{
+    net::UdpSocket,
+    time::{Duration, Instant},
+};
+
+use godot::{engine::global::Error, prelude::*};
+use log::{debug, error};
+
+use crate::{
+    data_parser::DataParser,
+    model::tracking_data::{IFacialMocapData, DEFAULT_I_FACIAL_MOCAP_PORT},
+};
+
+use super::{address_and_port, store_latest, ReceiveThread, Receiver, IFM_HANDSHAKE, IFM_PORT};
+
+/// The handshake is resent on this interval until iFacialMocap starts sending data.
+const HANDSHAKE_INTERVAL: Duration = Duration::from_secs(1);
+/// The phone is considered disconnected, and the handshake is resent, if no data
+/// is received for this long. iFacialMocap stops sending data when it is restarted.
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Receives tracking data from iFacialMocap. The handshake is sent to the phone at
+/// `address`:`port` and data is always sent back to [IFM_PORT].
+#[derive(Debug, GodotClass)]
+pub struct IFacialMocap {
+    address: String,
+    port: u16,
+
+    thread: ReceiveThread<Vec<u8>>,
+}
+
+#[godot_api]
+impl RefCountedVirtual for IFacialMocap {
+    fn init(_base: godot::obj::Base<Self::Base>) -> Self {
+        <Self as Receiver>::create(Dictionary::new())
+    }
+}
+
+#[godot_api]
+impl IFacialMocap {
+    /// Create a receiver from an options [Dictionary] containing the phone's
+    /// `address` and `port`.
+    #[func(rename = create)]
+    fn create_bound(options: Dictionary) -> Gd<IFacialMocap> {
+        Gd::new(<Self as Receiver>::create(options))
+    }
+
+    #[func(rename = start)]
+    fn start_bound(&mut self) -> Error {
+        self.start()
+    }
+
+    #[func(rename = stop)]
+    fn stop_bound(&mut self) -> Error {
+        self.stop()
+    }
+
+    /// Take the latest received frame. See [DataParser::ifacial_mocap] for the keys.
+    /// Empty if nothing was received since the last poll.
+    #[func(rename = poll)]
+    fn poll_bound(&mut self) -> Dictionary {
+        self.poll().unwrap_or_else(Dictionary::new)
+    }
+
+    #[func]
+    fn is_running(&self) -> bool {
+        self.thread.is_running()
+    }
+
+    /// Apply the latest received frame, if any, to a 3D puppet.
+    #[func]
+    fn handle_puppet3d(&mut self, mut puppet: Gd<Node3D>) {
+        if let Some(data) = self.take_latest() {
+            puppet.call(
+                "handle_i_facial_mocap".into(),
+                &[IFacialMocapData::from(data).to_variant()],
+            );
+        }
+    }
+}
+
+impl IFacialMocap {
+    fn take_latest(&mut self) -> Option<PackedByteArray> {
+        self.thread
+            .take_latest()
+            .map(|v| PackedByteArray::from(v.as_slice()))
+    }
+}
+
+impl Receiver for IFacialMocap {
+    fn create(options: Dictionary) -> Self {
+        let (address, port) = address_and_port(&options, DEFAULT_I_FACIAL_MOCAP_PORT);
+
+        Self {
+            address,
+            port,
+
+            thread: ReceiveThread::new("iFacialMocap"),
+        }
+    }
+
+    fn start(&mut self) -> Error {
+        if self.thread.already_started() {
+            return Error::ERR_ALREADY_IN_USE;
+        }
+
+        let socket = match UdpSocket::bind(("0.0.0.0", IFM_PORT)) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Unable to bind port {IFM_PORT}: {e}");
+                return Error::ERR_CANT_OPEN;
+            }
+        };
+
+        let remote = (self.address.clone(), self.port);
+        let latest = self.thread.latest();
+        let mut connected = false;
+        let mut last_handshake: Option<Instant> = None;
+        let mut last_packet = Instant::now();
+        self.thread.start(socket, move |socket, packet| {
+            if connected && last_packet.elapsed() >= DISCONNECT_TIMEOUT {
+                debug!("No data received from iFacialMocap, resending handshake");
+                connected = false;
+            }
+            if !connected && last_handshake.map_or(true, |v| v.elapsed() >= HANDSHAKE_INTERVAL) {
+                if let Err(e) =
+                    socket.send_to(IFM_HANDSHAKE.as_bytes(), (remote.0.as_str(), remote.1))
+                {
+                    error!("Unable to send handshake to {}:{}: {e}", remote.0, remote.1);
+                }
+                last_handshake = Some(Instant::now());
+            }
+
+            // Empty datagrams are not tracking data
+            let packet = match packet {
+                Some(v) if !v.is_empty() => v,
+                _ => return true,
+            };
+            connected = true;
+            last_packet = Instant::now();
+
+            store_latest(&latest, packet.to_vec())
+        })
+    }
+
+    fn stop(&mut self) -> Error {
+        self.thread.stop()
+    }
+
+    fn poll(&mut self) -> Option<Dictionary> {
+        self.take_latest().map(DataParser::ifacial_mocap)
+    }
+}
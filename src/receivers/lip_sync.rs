@@ -0,0 +1,305 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use godot::{engine::global::Error, prelude::*};
+use log::{debug, error};
+
+use super::Receiver;
+
+/// How often the analysis thread checks if it should stop.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+/// How many audio frames can wait for analysis. Further frames are dropped, since
+/// only the latest frame matters.
+const AUDIO_QUEUE_SIZE: usize = 4;
+/// Amplitudes below this are treated as silence.
+const DEFAULT_SILENCE_THRESHOLD: f32 = 0.02;
+/// Zero crossing rates, as a fraction of samples, above this are treated as
+/// fricatives like `s` and `f`, which are closest to the `ih` viseme.
+const HIGH_ZERO_CROSSING_RATE: f32 = 0.25;
+/// Zero crossing rates below this are treated as rounded vowels like `o` and `u`.
+const LOW_ZERO_CROSSING_RATE: f32 = 0.05;
+
+/// A coarse mouth shape guessed from audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Viseme {
+    Silence,
+    Aa,
+    Ih,
+    Ou,
+}
+
+impl AsRef<str> for Viseme {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::Silence => "sil",
+            Self::Aa => "aa",
+            Self::Ih => "ih",
+            Self::Ou => "ou",
+        }
+    }
+}
+
+/// The result of analyzing a single audio frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LipSyncFrame {
+    /// The RMS of the frame, from 0.0-1.0.
+    pub amplitude: f32,
+    pub viseme: Viseme,
+}
+
+impl LipSyncFrame {
+    /// Analyze PCM `samples` from -1.0-1.0. Only amplitude and zero crossings are
+    /// used, so the viseme is a rough guess.
+    pub fn analyze(samples: &[f32], silence_threshold: f32) -> Self {
+        if samples.is_empty() {
+            return Self {
+                amplitude: 0.0,
+                viseme: Viseme::Silence,
+            };
+        }
+
+        let amplitude = (samples.iter().map(|v| v * v).sum::<f32>() / samples.len() as f32)
+            .sqrt()
+            .clamp(0.0, 1.0);
+        let zero_crossing_rate = samples
+            .windows(2)
+            .filter(|v| (v[0] >= 0.0) != (v[1] >= 0.0))
+            .count() as f32
+            / samples.len() as f32;
+
+        let viseme = if amplitude < silence_threshold {
+            Viseme::Silence
+        } else if zero_crossing_rate > HIGH_ZERO_CROSSING_RATE {
+            Viseme::Ih
+        } else if zero_crossing_rate < LOW_ZERO_CROSSING_RATE {
+            Viseme::Ou
+        } else {
+            Viseme::Aa
+        };
+
+        Self { amplitude, viseme }
+    }
+
+    fn to_dict(self) -> Dictionary {
+        let mut r = Dictionary::new();
+
+        r.insert("amplitude", self.amplitude);
+        r.insert("viseme", GodotString::from(self.viseme.as_ref()));
+
+        r
+    }
+}
+
+/// Amplitude based lip sync. Audio frames are pushed from Godot and analyzed on
+/// a separate thread.
+///
+/// Speech recognition is not supported, so only amplitude and a coarse viseme
+/// are available.
+#[derive(Debug, GodotClass)]
+pub struct LipSync {
+    silence_threshold: f32,
+
+    running: Arc<AtomicBool>,
+    latest: Arc<Mutex<Option<LipSyncFrame>>>,
+    sender: Option<SyncSender<Vec<f32>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+#[godot_api]
+impl RefCountedVirtual for LipSync {
+    fn init(_base: godot::obj::Base<Self::Base>) -> Self {
+        <Self as Receiver>::create(Dictionary::new())
+    }
+}
+
+#[godot_api]
+impl LipSync {
+    /// Create a receiver from an options [Dictionary] containing `silence_threshold`.
+    #[func(rename = create)]
+    fn create_bound(options: Dictionary) -> Gd<LipSync> {
+        Gd::new(<Self as Receiver>::create(options))
+    }
+
+    #[func(rename = start)]
+    fn start_bound(&mut self) -> Error {
+        self.start()
+    }
+
+    #[func(rename = stop)]
+    fn stop_bound(&mut self) -> Error {
+        self.stop()
+    }
+
+    /// Take the latest analyzed frame. Contains an `amplitude` from 0.0-1.0 and a
+    /// `viseme` that is one of `sil`, `aa`, `ih`, or `ou`. Empty if nothing was
+    /// analyzed since the last poll.
+    #[func(rename = poll)]
+    fn poll_bound(&mut self) -> Dictionary {
+        self.poll().unwrap_or_else(Dictionary::new)
+    }
+
+    #[func]
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Queue mono PCM `samples` from -1.0-1.0 for analysis, e.g. from an
+    /// `AudioEffectCapture`. Ignored if the receiver is not running. Frames are
+    /// dropped if the analysis thread falls behind.
+    #[func]
+    fn push_audio(&mut self, samples: PackedFloat32Array) {
+        let sender = match self.sender.as_ref() {
+            Some(v) => v,
+            None => return,
+        };
+
+        match sender.try_send(samples.to_vec()) {
+            Ok(_) | Err(TrySendError::Full(_)) => {}
+            Err(e) => error!("Unable to queue audio: {e}"),
+        }
+    }
+
+    /// Apply the amplitude of the latest analyzed frame, if any, to a puppet.
+    #[func]
+    fn handle_puppet(&mut self, mut puppet: Gd<Node>) {
+        if let Some(frame) = self.take_latest() {
+            puppet.call("handle_lip_sync".into(), &[frame.amplitude.to_variant()]);
+        }
+    }
+}
+
+impl LipSync {
+    fn take_latest(&mut self) -> Option<LipSyncFrame> {
+        self.latest.lock().ok()?.take()
+    }
+}
+
+impl Receiver for LipSync {
+    fn create(options: Dictionary) -> Self {
+        let silence_threshold = options
+            .get("silence_threshold")
+            .and_then(|v| v.try_to::<f32>().ok())
+            .unwrap_or(DEFAULT_SILENCE_THRESHOLD);
+
+        Self {
+            silence_threshold,
+
+            running: Arc::new(AtomicBool::new(false)),
+            latest: Arc::new(Mutex::new(None)),
+            sender: None,
+            handle: None,
+        }
+    }
+
+    fn start(&mut self) -> Error {
+        if self.handle.is_some() {
+            error!("Lip sync receiver is already running");
+            return Error::ERR_ALREADY_IN_USE;
+        }
+
+        let (sender, receiver) = mpsc::sync_channel::<Vec<f32>>(AUDIO_QUEUE_SIZE);
+        self.sender = Some(sender);
+        self.running.store(true, Ordering::Relaxed);
+
+        let running = self.running.clone();
+        let latest = self.latest.clone();
+        let silence_threshold = self.silence_threshold;
+        self.handle = Some(std::thread::spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                match receiver.recv_timeout(READ_TIMEOUT) {
+                    Ok(samples) => {
+                        // Skip to the newest queued frame, older frames are already stale
+                        let samples = receiver.try_iter().last().unwrap_or(samples);
+                        let frame = LipSyncFrame::analyze(&samples, silence_threshold);
+                        match latest.lock() {
+                            Ok(mut v) => {
+                                v.replace(frame);
+                            }
+                            Err(e) => {
+                                error!("{e}");
+                                break;
+                            }
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+
+            running.store(false, Ordering::Relaxed);
+            debug!("Lip sync receiver stopped");
+        }));
+
+        Error::OK
+    }
+
+    fn stop(&mut self) -> Error {
+        self.running.store(false, Ordering::Relaxed);
+        self.sender = None;
+
+        match self.handle.take() {
+            Some(v) => match v.join() {
+                Ok(_) => Error::OK,
+                Err(e) => {
+                    error!("Lip sync receiver panicked: {e:?}");
+                    Error::ERR_BUG
+                }
+            },
+            None => Error::OK,
+        }
+    }
+
+    fn poll(&mut self) -> Option<Dictionary> {
+        self.take_latest().map(|v| v.to_dict())
+    }
+}
+
+impl Drop for LipSync {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silence() {
+        let frame = LipSyncFrame::analyze(&[0.0; 256], DEFAULT_SILENCE_THRESHOLD);
+        assert_eq!(frame.amplitude, 0.0);
+        assert_eq!(frame.viseme, Viseme::Silence);
+
+        let frame = LipSyncFrame::analyze(&[], DEFAULT_SILENCE_THRESHOLD);
+        assert_eq!(frame.viseme, Viseme::Silence);
+    }
+
+    #[test]
+    fn visemes_from_zero_crossings() {
+        // Alternating every sample, like noise from fricatives
+        let high = (0..256)
+            .map(|i| if i % 2 == 0 { 0.5 } else { -0.5 })
+            .collect::<Vec<f32>>();
+        let frame = LipSyncFrame::analyze(&high, DEFAULT_SILENCE_THRESHOLD);
+        assert_eq!(frame.amplitude, 0.5);
+        assert_eq!(frame.viseme, Viseme::Ih);
+
+        // Alternating every 8 samples
+        let mid = (0..256)
+            .map(|i| if (i / 8) % 2 == 0 { 0.5 } else { -0.5 })
+            .collect::<Vec<f32>>();
+        let frame = LipSyncFrame::analyze(&mid, DEFAULT_SILENCE_THRESHOLD);
+        assert_eq!(frame.viseme, Viseme::Aa);
+
+        let low = [0.5; 256];
+        let frame = LipSyncFrame::analyze(&low, DEFAULT_SILENCE_THRESHOLD);
+        assert_eq!(frame.viseme, Viseme::Ou);
+    }
+}
@@ -0,0 +1,119 @@
+use std::net::UdpSocket;
+
+use godot::{engine::global::Error, prelude::*};
+use log::error;
+
+use crate::{data_parser::OpenSeeFaceData, model::tracking_data::DEFAULT_OPEN_SEE_FACE_PORT};
+
+use super::{address_and_port, store_latest, ReceiveThread, Receiver};
+
+/// Receives tracking data from OpenSeeFace. OpenSeeFace sends data to a given
+/// address without needing a request, so this only listens.
+#[derive(Debug, GodotClass)]
+pub struct OpenSeeFace {
+    address: String,
+    port: u16,
+
+    thread: ReceiveThread<OpenSeeFaceData>,
+}
+
+#[godot_api]
+impl RefCountedVirtual for OpenSeeFace {
+    fn init(_base: godot::obj::Base<Self::Base>) -> Self {
+        <Self as Receiver>::create(Dictionary::new())
+    }
+}
+
+#[godot_api]
+impl OpenSeeFace {
+    /// Create a receiver from an options [Dictionary] containing `address` and `port`.
+    #[func(rename = create)]
+    fn create_bound(options: Dictionary) -> Gd<OpenSeeFace> {
+        Gd::new(<Self as Receiver>::create(options))
+    }
+
+    #[func(rename = start)]
+    fn start_bound(&mut self) -> Error {
+        self.start()
+    }
+
+    #[func(rename = stop)]
+    fn stop_bound(&mut self) -> Error {
+        self.stop()
+    }
+
+    /// Take the latest received frame. See [crate::data_parser::DataParser::open_see_face]
+    /// for the keys. Empty if nothing was received since the last poll.
+    #[func(rename = poll)]
+    fn poll_bound(&mut self) -> Dictionary {
+        self.poll().unwrap_or_else(Dictionary::new)
+    }
+
+    #[func]
+    fn is_running(&self) -> bool {
+        self.thread.is_running()
+    }
+
+    /// Apply the latest received frame, if any, to a 3D puppet.
+    #[func]
+    fn handle_puppet3d(&mut self, mut puppet: Gd<Node3D>) {
+        if let Some(data) = self.poll() {
+            puppet.call("handle_open_see_face".into(), &[data.to_variant()]);
+        }
+    }
+}
+
+impl Receiver for OpenSeeFace {
+    fn create(options: Dictionary) -> Self {
+        let (address, port) = address_and_port(&options, DEFAULT_OPEN_SEE_FACE_PORT);
+
+        Self {
+            address,
+            port,
+
+            thread: ReceiveThread::new("OpenSeeFace"),
+        }
+    }
+
+    fn start(&mut self) -> Error {
+        if self.thread.already_started() {
+            return Error::ERR_ALREADY_IN_USE;
+        }
+
+        let socket = match UdpSocket::bind((self.address.as_str(), self.port)) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Unable to bind {}:{}: {e}", self.address, self.port);
+                return Error::ERR_CANT_OPEN;
+            }
+        };
+
+        let latest = self.thread.latest();
+        self.thread.start(socket, move |_, packet| {
+            let packet = match packet {
+                Some(v) => v,
+                None => return true,
+            };
+
+            // Packets contain all tracked faces, only the first face is used
+            match OpenSeeFaceData::parse(packet) {
+                Some(data) => store_latest(&latest, data),
+                None => {
+                    error!(
+                        "Received truncated OpenSeeFace packet of {} bytes",
+                        packet.len()
+                    );
+                    true
+                }
+            }
+        })
+    }
+
+    fn stop(&mut self) -> Error {
+        self.thread.stop()
+    }
+
+    fn poll(&mut self) -> Option<Dictionary> {
+        self.thread.take_latest().map(|v| v.to_dict())
+    }
+}
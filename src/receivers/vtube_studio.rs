@@ -0,0 +1,150 @@
+use std::{
+    net::UdpSocket,
+    time::{Duration, Instant},
+};
+
+use godot::{engine::global::Error, prelude::*};
+use log::error;
+
+use crate::{
+    data_parser::DataParser,
+    model::tracking_data::{VTubeStudioData, DEFAULT_VTUBE_STUDIO_PORT},
+};
+
+use super::{address_and_port, ios_tracking_data_request, store_latest, ReceiveThread, Receiver};
+
+/// VTubeStudio only sends data for a short time after each request, so requests
+/// are resent on this interval.
+const REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Receives tracking data from the VTubeStudio iOS app using the iOS tracking data
+/// protocol. Requests are sent to the phone at `address`:`port` and data is sent
+/// back to a local port.
+///
+/// Only the iOS tracking protocol is supported. The VTubeStudio API websocket,
+/// including its token authentication, is not implemented.
+#[derive(Debug, GodotClass)]
+pub struct VTubeStudio {
+    address: String,
+    port: u16,
+
+    thread: ReceiveThread<Vec<u8>>,
+}
+
+#[godot_api]
+impl RefCountedVirtual for VTubeStudio {
+    fn init(_base: godot::obj::Base<Self::Base>) -> Self {
+        <Self as Receiver>::create(Dictionary::new())
+    }
+}
+
+#[godot_api]
+impl VTubeStudio {
+    /// Create a receiver from an options [Dictionary] containing the phone's
+    /// `address` and `port`.
+    #[func(rename = create)]
+    fn create_bound(options: Dictionary) -> Gd<VTubeStudio> {
+        Gd::new(<Self as Receiver>::create(options))
+    }
+
+    #[func(rename = start)]
+    fn start_bound(&mut self) -> Error {
+        self.start()
+    }
+
+    #[func(rename = stop)]
+    fn stop_bound(&mut self) -> Error {
+        self.stop()
+    }
+
+    /// Take the latest received frame. See [DataParser::vtube_studio] for the keys.
+    /// Empty if nothing was received since the last poll.
+    #[func(rename = poll)]
+    fn poll_bound(&mut self) -> Dictionary {
+        self.poll().unwrap_or_else(Dictionary::new)
+    }
+
+    #[func]
+    fn is_running(&self) -> bool {
+        self.thread.is_running()
+    }
+
+    /// Apply the latest received frame, if any, to a 3D puppet.
+    #[func]
+    fn handle_puppet3d(&mut self, mut puppet: Gd<Node3D>) {
+        if let Some(data) = self.take_latest() {
+            puppet.call(
+                "handle_vtube_studio".into(),
+                &[VTubeStudioData::from(data).to_variant()],
+            );
+        }
+    }
+}
+
+impl VTubeStudio {
+    fn take_latest(&mut self) -> Option<PackedByteArray> {
+        self.thread
+            .take_latest()
+            .map(|v| PackedByteArray::from(v.as_slice()))
+    }
+}
+
+impl Receiver for VTubeStudio {
+    fn create(options: Dictionary) -> Self {
+        let (address, port) = address_and_port(&options, DEFAULT_VTUBE_STUDIO_PORT);
+
+        Self {
+            address,
+            port,
+
+            thread: ReceiveThread::new("VTubeStudio"),
+        }
+    }
+
+    fn start(&mut self) -> Error {
+        if self.thread.already_started() {
+            return Error::ERR_ALREADY_IN_USE;
+        }
+
+        let socket = match UdpSocket::bind("0.0.0.0:0") {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Unable to bind local socket: {e}");
+                return Error::ERR_CANT_OPEN;
+            }
+        };
+        let request = match socket.local_addr() {
+            Ok(v) => ios_tracking_data_request(v.port()),
+            Err(e) => {
+                error!("{e}");
+                return Error::ERR_CANT_OPEN;
+            }
+        };
+
+        let remote = (self.address.clone(), self.port);
+        let latest = self.thread.latest();
+        let mut last_request: Option<Instant> = None;
+        self.thread.start(socket, move |socket, packet| {
+            if last_request.map_or(true, |v| v.elapsed() >= REQUEST_INTERVAL) {
+                // The phone may not be reachable yet, so keep trying
+                if let Err(e) = socket.send_to(request.as_bytes(), (remote.0.as_str(), remote.1)) {
+                    error!("Unable to send request to {}:{}: {e}", remote.0, remote.1);
+                }
+                last_request = Some(Instant::now());
+            }
+
+            match packet {
+                Some(v) => store_latest(&latest, v.to_vec()),
+                None => true,
+            }
+        })
+    }
+
+    fn stop(&mut self) -> Error {
+        self.thread.stop()
+    }
+
+    fn poll(&mut self) -> Option<Dictionary> {
+        self.take_latest().map(DataParser::vtube_studio)
+    }
+}